@@ -0,0 +1,174 @@
+use nix::sys::signal;
+use nix::sys::stat::{self, Mode};
+use nix::unistd::{self, ForkResult, Pid};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, process};
+
+use crate::Config;
+
+// Detaches from the terminal with the classic double fork, and writes the pid of the daemon to
+// `pidfile`, which is removed when the returned guard is dropped. Must be called before any
+// thread is started.
+pub fn daemonize(pidfile: &Path) -> Result<Pidfile, Box<dyn Error>> {
+    // Fail while the error can still be seen.
+    check_not_running(pidfile)?;
+
+    // SAFETY: the process has a single thread, so the child can run any code.
+    if let ForkResult::Parent { .. } = unsafe { unistd::fork() }? {
+        process::exit(0);
+    }
+    // A new session has no controlling terminal, and forking again makes sure that the daemon,
+    // which does not lead the session, can never acquire one.
+    unistd::setsid()?;
+    // SAFETY: as above.
+    if let ForkResult::Parent { .. } = unsafe { unistd::fork() }? {
+        process::exit(0);
+    }
+
+    stat::umask(Mode::from_bits_truncate(0o022));
+    env::set_current_dir("/")?;
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    for fd in 0..=2 {
+        unistd::dup2(null.as_raw_fd(), fd)?;
+    }
+
+    fs::write(pidfile, format!("{}\n", process::id()))?;
+    Ok(Pidfile(pidfile.to_path_buf()))
+}
+
+fn check_not_running(pidfile: &Path) -> Result<(), Box<dyn Error>> {
+    let Ok(contents) = fs::read_to_string(pidfile) else {
+        return Ok(());
+    };
+    match contents.trim().parse() {
+        // A pidfile left by a daemon that did not exit cleanly is replaced.
+        Ok(pid) if signal::kill(Pid::from_raw(pid), None).is_ok() => Err(format!(
+            "daemonfsd is already running with pid {pid}, see {}",
+            pidfile.display()
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+pub struct Pidfile(PathBuf);
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// Where the pidfile goes by default.
+pub fn default_pidfile() -> PathBuf {
+    let dir = env::var_os("XDG_RUNTIME_DIR").map_or_else(env::temp_dir, PathBuf::from);
+    dir.join("daemonfsd.pid")
+}
+
+// Tells systemd about the state of the service, like "READY=1", when it was started by a
+// Type=notify unit. Does nothing otherwise.
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(socket) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let address = match socket.as_bytes().strip_prefix(b"@") {
+        // A socket in the abstract namespace.
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+// How often to tell the systemd watchdog that the service is alive, when it has one.
+pub fn watchdog_interval() -> Option<Duration> {
+    // The variables may be inherited by processes that are not the service.
+    if let Some(pid) = env::var_os("WATCHDOG_PID") {
+        if pid != OsStr::new(&process::id().to_string()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // Twice per timeout, as systemd recommends.
+    Some(Duration::from_micros(usec) / 2)
+}
+
+// Writes a systemd user unit that runs daemonfsd with `config`, and returns its path.
+pub fn install(config: &Config) -> Result<PathBuf, Box<dyn Error>> {
+    let exe = env::current_exe()?;
+    let mut command = vec![exe.display().to_string()];
+    command.push(String::from("--debounce"));
+    command.push(config.debounce.as_millis().to_string());
+    command.push(String::from("--rate-limit"));
+    command.push(config.rate_limit.to_string());
+    if let Some(rules) = &config.rules {
+        command.push(String::from("--rules"));
+        command.push(fs::canonicalize(rules)?.display().to_string());
+    }
+    let root = fs::canonicalize(&config.path)?.display().to_string();
+    command.push(root.clone());
+    let command: Vec<String> = command.iter().map(|arg| quote(arg)).collect();
+
+    let unit = format!(
+        "[Unit]\n\
+         Description=DaemonFSD file watcher for {path}\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={exec}\n\
+         ExecReload=/bin/kill -HUP $MAINPID\n\
+         WatchdogSec=30\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        path = root.replace('%', "%%"),
+        exec = command.join(" "),
+    );
+
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok_or("Neither XDG_CONFIG_HOME nor HOME is set")?;
+    let dir = config_home.join("systemd/user");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("daemonfsd.service");
+    fs::write(&path, unit)?;
+    Ok(path)
+}
+
+// Quotes an argument of a unit's ExecStart, where `%` starts a specifier and `$` a variable.
+fn quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    match escaped.contains(char::is_whitespace) {
+        true => format!("\"{escaped}\""),
+        false => escaped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_exec_arguments() {
+        assert_eq!("/usr/bin/daemonfsd", quote("/usr/bin/daemonfsd"));
+        assert_eq!("\"/home/me/My Documents\"", quote("/home/me/My Documents"));
+        assert_eq!("100%%", quote("100%"));
+        assert_eq!("$$HOME", quote("$HOME"));
+        assert_eq!("\"a \\\"b\\\" c:\\\\d\"", quote("a \"b\" c:\\d"));
+    }
+}
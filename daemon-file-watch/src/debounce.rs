@@ -0,0 +1,182 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::event::{self, Change, ChangeKind};
+
+// Holds back the changes of each path until it has been quiet for a while, so that the bursts of
+// events of a single save are reported once.
+pub struct Debouncer {
+    window: Duration,
+    changes: Vec<Change>,
+    // When the change of each path can be reported, unless it changes again.
+    deadlines: HashMap<PathBuf, Instant>,
+}
+
+impl Debouncer {
+    pub fn new(window: Duration) -> Debouncer {
+        Debouncer {
+            window,
+            changes: Vec::new(),
+            deadlines: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, changes: Vec<Change>, now: Instant) {
+        for mut change in changes {
+            if let ChangeKind::Renamed { from } = &change.kind {
+                // The change still pending for the old path now belongs to the new one.
+                if let Some(index) = self.changes.iter().position(|other| other.path == *from) {
+                    self.deadlines.remove(from);
+                    let previous = self.changes.remove(index).kind;
+                    change.kind = ChangeKind::renamed(
+                        Some(previous),
+                        from.clone(),
+                        &change.path,
+                        change.is_dir,
+                    );
+                }
+            }
+            self.deadlines
+                .insert(change.path.clone(), now + self.window);
+            event::record(&mut self.changes, change);
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.deadlines.values().min().copied()
+    }
+
+    // Removes and returns the changes of the paths that have been quiet for the whole window.
+    pub fn take_ready(&mut self, now: Instant) -> Vec<Change> {
+        self.deadlines.retain(|_, deadline| *deadline > now);
+        let (pending, ready) = self
+            .changes
+            .drain(..)
+            .partition(|change| self.deadlines.contains_key(&change.path));
+        self.changes = pending;
+        ready
+    }
+}
+
+// Allows at most `max` notifications in any `period`.
+pub struct RateLimiter {
+    max: usize,
+    period: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(max: usize, period: Duration) -> RateLimiter {
+        RateLimiter {
+            max,
+            period,
+            sent: VecDeque::with_capacity(max),
+        }
+    }
+
+    // When the next notification can be sent.
+    pub fn next_allowed(&mut self, now: Instant) -> Instant {
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| *sent + self.period <= now)
+        {
+            self.sent.pop_front();
+        }
+        match self.sent.len() < self.max {
+            true => now,
+            false => self.sent[self.sent.len() - self.max] + self.period,
+        }
+    }
+
+    // Records a notification sent at `now`, if one is allowed then.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        if self.next_allowed(now) > now {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(100);
+
+    fn change(path: &str, kind: ChangeKind) -> Change {
+        Change {
+            path: PathBuf::from(path),
+            kind,
+            is_dir: false,
+        }
+    }
+
+    fn renamed(from: &str, to: &str) -> Change {
+        let from = PathBuf::from(from);
+        change(to, ChangeKind::Renamed { from })
+    }
+
+    #[test]
+    fn changes_wait_for_quiet() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(WINDOW);
+        assert_eq!(None, debouncer.next_deadline());
+
+        debouncer.add(vec![change("a", ChangeKind::Modified)], start);
+        debouncer.add(vec![change("b", ChangeKind::Created)], start + WINDOW / 2);
+        debouncer.add(vec![change("a", ChangeKind::Written)], start + WINDOW / 2);
+        assert_eq!(Some(start + WINDOW * 3 / 2), debouncer.next_deadline());
+        assert_eq!(Vec::<Change>::new(), debouncer.take_ready(start + WINDOW));
+
+        assert_eq!(
+            vec![
+                change("a", ChangeKind::Written),
+                change("b", ChangeKind::Created)
+            ],
+            debouncer.take_ready(start + WINDOW * 3 / 2)
+        );
+        assert_eq!(None, debouncer.next_deadline());
+    }
+
+    #[test]
+    fn renames_move_pending_changes() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(WINDOW);
+        debouncer.add(vec![change("a", ChangeKind::Created)], start);
+        debouncer.add(vec![change("b", ChangeKind::Modified)], start);
+        debouncer.add(vec![renamed("a", "c"), renamed("b", "d")], start);
+        debouncer.add(vec![renamed("d", "e")], start);
+        assert_eq!(
+            vec![change("c", ChangeKind::Written), renamed("b", "e")],
+            debouncer.take_ready(start + WINDOW)
+        );
+
+        debouncer.add(vec![renamed("a", "b")], start);
+        debouncer.add(vec![renamed("b", "a")], start);
+        assert_eq!(
+            vec![change("a", ChangeKind::Modified)],
+            debouncer.take_ready(start + WINDOW)
+        );
+    }
+
+    #[test]
+    fn rate_limiter_allows_max_per_period() {
+        let start = Instant::now();
+        let period = Duration::from_secs(10);
+        let mut limiter = RateLimiter::new(2, period);
+        assert!(limiter.try_acquire(start));
+        assert!(limiter.try_acquire(start + Duration::from_secs(4)));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(5)));
+        assert_eq!(
+            start + period,
+            limiter.next_allowed(start + Duration::from_secs(5))
+        );
+
+        assert!(limiter.try_acquire(start + period));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(12)));
+        assert!(limiter.try_acquire(start + Duration::from_secs(14)));
+    }
+}
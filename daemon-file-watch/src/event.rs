@@ -0,0 +1,417 @@
+use nix::sys::inotify::{AddWatchFlags, InotifyEvent};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::watch::Watcher;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Deleted,
+    Modified,
+    // Written and closed.
+    Written,
+    // The watched path itself was moved.
+    Moved,
+    // Moved into the tree from outside of it.
+    MovedIn,
+    // Moved out of the tree.
+    MovedOut,
+    Renamed { from: PathBuf },
+}
+
+impl ChangeKind {
+    // Every kind, with an empty path for renames.
+    fn all() -> [ChangeKind; 8] {
+        use ChangeKind::*;
+        [
+            Created,
+            Deleted,
+            Modified,
+            Written,
+            Moved,
+            MovedIn,
+            MovedOut,
+            Renamed {
+                from: PathBuf::new(),
+            },
+        ]
+    }
+
+    // The names of the kinds, as in rules files and JSON records.
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        ChangeKind::all().into_iter().map(|kind| kind.name())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Deleted => "deleted",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Written => "written",
+            ChangeKind::Moved => "moved",
+            ChangeKind::MovedIn => "moved_in",
+            ChangeKind::MovedOut => "moved_out",
+            ChangeKind::Renamed { .. } => "renamed",
+        }
+    }
+
+    fn of(mask: AddWatchFlags) -> Option<ChangeKind> {
+        if mask.contains(AddWatchFlags::IN_CREATE) {
+            Some(ChangeKind::Created)
+        } else if mask.intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_DELETE_SELF) {
+            Some(ChangeKind::Deleted)
+        } else if mask.contains(AddWatchFlags::IN_MODIFY) {
+            Some(ChangeKind::Modified)
+        } else if mask.contains(AddWatchFlags::IN_CLOSE_WRITE) {
+            Some(ChangeKind::Written)
+        } else if mask.contains(AddWatchFlags::IN_MOVE_SELF) {
+            Some(ChangeKind::Moved)
+        } else if mask.contains(AddWatchFlags::IN_MOVED_TO) {
+            Some(ChangeKind::MovedIn)
+        } else if mask.contains(AddWatchFlags::IN_MOVED_FROM) {
+            Some(ChangeKind::MovedOut)
+        } else {
+            None
+        }
+    }
+
+    // The change of `to` when it is renamed from `from`, whose earlier change was `previous`.
+    // inotify does not report the file that a rename replaces, so a file created and renamed, like
+    // an editor saving through a temporary file, is reported as written rather than created.
+    pub fn renamed(
+        previous: Option<ChangeKind>,
+        from: PathBuf,
+        to: &Path,
+        is_dir: bool,
+    ) -> ChangeKind {
+        match previous {
+            Some(ChangeKind::Created) if !is_dir => ChangeKind::Written,
+            Some(kind @ (ChangeKind::Created | ChangeKind::MovedIn)) => kind,
+            // Renamed back to where it was.
+            Some(ChangeKind::Renamed { from: original }) if original == to => ChangeKind::Modified,
+            Some(ChangeKind::Renamed { from: original }) => ChangeKind::Renamed { from: original },
+            _ => ChangeKind::Renamed { from },
+        }
+    }
+
+    // The change that `next` makes after `self`, or `None` when together they change nothing.
+    fn then(self, next: ChangeKind) -> Option<ChangeKind> {
+        use ChangeKind::*;
+        match (self, next) {
+            (Created | MovedIn, Deleted | MovedOut) => None,
+            (previous @ (Created | MovedIn | Renamed { .. }), Modified | Written) => Some(previous),
+            (Written, Modified) | (Modified, Written) => Some(Written),
+            // The file was replaced.
+            (Deleted | MovedOut, Created | MovedIn | Renamed { .. }) => Some(Modified),
+            (_, next) => Some(next),
+        }
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChangeKind::Created => write!(f, "created"),
+            ChangeKind::Deleted => write!(f, "deleted"),
+            ChangeKind::Modified => write!(f, "modified"),
+            ChangeKind::Written => write!(f, "written and closed"),
+            ChangeKind::Moved => write!(f, "moved"),
+            ChangeKind::MovedIn => write!(f, "moved in"),
+            ChangeKind::MovedOut => write!(f, "moved out"),
+            ChangeKind::Renamed { .. } => write!(f, "renamed"),
+        }
+    }
+}
+
+// What happened to a path during a batch of events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    pub is_dir: bool,
+}
+
+impl Change {
+    // Describes the change, with paths shown relative to the watched `root`, under its `name`.
+    pub fn describe(&self, root: &Path, name: &str) -> String {
+        let show = |path: &Path| {
+            let mut shown = PathBuf::from(name);
+            if let Ok(relative) = path.strip_prefix(root) {
+                shown.push(relative);
+            }
+            match self.is_dir {
+                true => format!("{}/", shown.display()),
+                false => shown.display().to_string(),
+            }
+        };
+        match &self.kind {
+            ChangeKind::Renamed { from } => {
+                format!("{} renamed to {}", show(from), show(&self.path))
+            }
+            kind => format!("{} {kind}", show(&self.path)),
+        }
+    }
+}
+
+// Coalesces a batch of events into one change per path, in the order the paths first changed.
+// A file moved within the tree is reported as renamed when both halves of the move are in the
+// batch, which the kernel pairs with a cookie.
+pub fn coalesce(watcher: &Watcher, events: &[InotifyEvent]) -> Vec<Change> {
+    let mut changes: Vec<Change> = Vec::new();
+    // The files moved away in this batch, with the cookie of their move.
+    let mut moved_from: Vec<(u32, PathBuf, bool)> = Vec::new();
+
+    for event in events {
+        let Some(mut kind) = ChangeKind::of(event.mask) else {
+            continue;
+        };
+        let Some(path) = watcher.path(event) else {
+            continue;
+        };
+        // The parent directory already reports the deletion and moves of its subdirectories.
+        let about_self = event.name.is_none();
+        if about_self && path != watcher.root() {
+            continue;
+        }
+
+        let is_dir = event.mask.contains(AddWatchFlags::IN_ISDIR);
+        match kind {
+            ChangeKind::MovedOut => {
+                moved_from.push((event.cookie, path, is_dir));
+                continue;
+            }
+            ChangeKind::MovedIn => {
+                let paired = moved_from
+                    .iter()
+                    .position(|(cookie, _, _)| *cookie == event.cookie);
+                if let Some((_, from, _)) = paired.map(|index| moved_from.remove(index)) {
+                    let previous = changes.iter().position(|change| change.path == from);
+                    let previous = previous.map(|index| changes.remove(index).kind);
+                    kind = ChangeKind::renamed(previous, from, &path, is_dir);
+                }
+            }
+            _ => {}
+        }
+        record(&mut changes, Change { path, kind, is_dir });
+    }
+
+    for (_, path, is_dir) in moved_from {
+        let kind = ChangeKind::MovedOut;
+        record(&mut changes, Change { path, kind, is_dir });
+    }
+    changes
+}
+
+// Adds `change` to `changes`, merged with the earlier change of the same path.
+pub fn record(changes: &mut Vec<Change>, change: Change) {
+    let Some(index) = changes.iter().position(|other| other.path == change.path) else {
+        changes.push(change);
+        return;
+    };
+    let previous = changes.remove(index).kind;
+    if let Some(kind) = previous.then(change.kind) {
+        changes.insert(index, Change { kind, ..change });
+    }
+}
+
+// The lines of a notification body listed before the others are counted.
+const LISTED_CHANGES: usize = 5;
+
+// Summarizes changes for a notification: a single change is described, and several are counted
+// by the directory that contains them all, like "3 files changed in ~/docs".
+pub fn summarize(changes: &[Change], root: &Path, name: &str) -> String {
+    let lines: Vec<String> = changes
+        .iter()
+        .map(|change| change.describe(root, name))
+        .collect();
+    if let [line] = lines.as_slice() {
+        return line.clone();
+    }
+
+    let mut summary = format!(
+        "{} files changed in {}",
+        changes.len(),
+        abbreviate_home(&common_dir(changes))
+    );
+    for line in lines.iter().take(LISTED_CHANGES) {
+        summary.push('\n');
+        summary.push_str(line);
+    }
+    if lines.len() > LISTED_CHANGES {
+        summary.push_str(&format!("\nand {} more", lines.len() - LISTED_CHANGES));
+    }
+    summary
+}
+
+// The deepest directory that contains every changed path.
+fn common_dir(changes: &[Change]) -> PathBuf {
+    let mut paths = changes
+        .iter()
+        .map(|change| change.path.parent().unwrap_or(&change.path));
+    let mut common = paths.next().map(Path::to_path_buf).unwrap_or_default();
+    for path in paths {
+        while !path.starts_with(&common) {
+            if !common.pop() {
+                break;
+            }
+        }
+    }
+    common
+}
+
+fn abbreviate_home(path: &Path) -> String {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    match home
+        .as_deref()
+        .and_then(|home| path.strip_prefix(home).ok())
+    {
+        Some(relative) if relative.as_os_str().is_empty() => String::from("~"),
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn change(path: &str, kind: ChangeKind) -> Change {
+        Change {
+            path: PathBuf::from(path),
+            kind,
+            is_dir: false,
+        }
+    }
+
+    // The changes of the events of the operations `change` makes in a watched directory.
+    fn watch(prepare: impl FnOnce(&Path), change: impl FnOnce(&Path)) -> (PathBuf, Vec<Change>) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        prepare(&root);
+        let mut watcher = Watcher::new(&root).unwrap();
+        change(&root);
+        let events = watcher.read_events().unwrap();
+        (root, coalesce(&watcher, &events))
+    }
+
+    #[test]
+    fn names_are_distinct() {
+        let mut names: Vec<_> = ChangeKind::names().collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(ChangeKind::all().len(), names.len());
+    }
+
+    #[test]
+    fn then_merges_kinds() {
+        use ChangeKind::*;
+        assert_eq!(Some(Created), Created.then(Modified));
+        assert_eq!(Some(MovedIn), MovedIn.then(Written));
+        assert_eq!(None, Created.then(Deleted));
+        assert_eq!(None, MovedIn.then(MovedOut));
+        assert_eq!(Some(Written), Modified.then(Written));
+        assert_eq!(Some(Written), Written.then(Modified));
+        assert_eq!(Some(Modified), Deleted.then(Created));
+        assert_eq!(Some(Deleted), Modified.then(Deleted));
+        let renamed = Renamed {
+            from: PathBuf::from("a"),
+        };
+        assert_eq!(Some(renamed.clone()), renamed.then(Modified));
+    }
+
+    #[test]
+    fn record_merges_changes_of_a_path() {
+        let mut changes = Vec::new();
+        record(&mut changes, change("a", ChangeKind::Created));
+        record(&mut changes, change("b", ChangeKind::Modified));
+        record(&mut changes, change("a", ChangeKind::Written));
+        assert_eq!(
+            vec![
+                change("a", ChangeKind::Created),
+                change("b", ChangeKind::Modified)
+            ],
+            changes
+        );
+
+        record(&mut changes, change("a", ChangeKind::Deleted));
+        assert_eq!(vec![change("b", ChangeKind::Modified)], changes);
+    }
+
+    #[test]
+    fn coalesce_pairs_renames() {
+        let (root, changes) = watch(
+            |root| fs::write(root.join("a"), "a").unwrap(),
+            |root| fs::rename(root.join("a"), root.join("b")).unwrap(),
+        );
+        let kind = ChangeKind::Renamed {
+            from: root.join("a"),
+        };
+        assert_eq!(
+            vec![Change {
+                path: root.join("b"),
+                kind,
+                is_dir: false
+            }],
+            changes
+        );
+    }
+
+    #[test]
+    fn coalesce_drops_created_then_deleted() {
+        let (_, changes) = watch(
+            |_| {},
+            |root| {
+                fs::write(root.join("a"), "a").unwrap();
+                fs::remove_file(root.join("a")).unwrap();
+            },
+        );
+        assert_eq!(Vec::<Change>::new(), changes);
+    }
+
+    #[test]
+    fn coalesce_writes_created_then_renamed() {
+        // Whether `b` existed is unknown, so its content is reported as written.
+        for existing in [false, true] {
+            let (root, changes) = watch(
+                |root| {
+                    if existing {
+                        fs::write(root.join("b"), "old").unwrap();
+                    }
+                },
+                |root| {
+                    fs::write(root.join(".b.tmp"), "new").unwrap();
+                    fs::rename(root.join(".b.tmp"), root.join("b")).unwrap();
+                },
+            );
+            assert_eq!(
+                vec![Change {
+                    path: root.join("b"),
+                    kind: ChangeKind::Written,
+                    is_dir: false
+                }],
+                changes
+            );
+        }
+    }
+
+    #[test]
+    fn coalesce_keeps_directories_created_then_renamed() {
+        let (root, changes) = watch(
+            |_| {},
+            |root| {
+                fs::create_dir(root.join("a")).unwrap();
+                fs::rename(root.join("a"), root.join("b")).unwrap();
+            },
+        );
+        assert_eq!(
+            vec![Change {
+                path: root.join("b"),
+                kind: ChangeKind::Created,
+                is_dir: true
+            }],
+            changes
+        );
+    }
+}
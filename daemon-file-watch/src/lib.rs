@@ -1,90 +1,267 @@
+mod daemon;
+mod debounce;
+mod event;
+mod log;
+mod rules;
+mod watch;
+
+use debounce::{Debouncer, RateLimiter};
+use event::Change;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
 use nix::libc;
-use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
 use nix::sys::signal::{self, SigHandler, Signal};
+use nix::unistd;
 use notify_rust::Notification;
+use rules::Rules;
 use std::error::Error;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::{fs, process};
+use std::fs;
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd};
+use std::path::{self, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+use watch::Watcher;
 
 // Global flag to detect signals
 static SIGNAL_RECEIVED: AtomicBool = AtomicBool::new(false);
+// Set by SIGHUP to reload the rules file. The other options keep the values they were started
+// with.
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+// The write end of the pipe that wakes up the watch loop when a signal is received, so that a
+// signal received just before it waits is not left unhandled until the next event.
+static WAKEUP_PIPE: AtomicI32 = AtomicI32::new(-1);
 
 extern "C" fn exit_signal_handler(_sig: libc::c_int) {
-    println!("Signal received, cleaning up...");
     SIGNAL_RECEIVED.store(true, Ordering::SeqCst);
+    wake_up();
+}
+
+extern "C" fn reload_signal_handler(_sig: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+    wake_up();
+}
+
+fn wake_up() {
+    // The interrupted code may be about to read errno.
+    let errno = Errno::last_raw();
+    let fd = WAKEUP_PIPE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // SAFETY: write is async-signal-safe. The pipe does not block, and a full one already
+        // wakes the loop up.
+        unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) };
+    }
+    Errno::set_raw(errno);
 }
 
-fn setup_signal_handlers() {
+// Installs the signal handlers, and returns the read end of the pipe they write to.
+fn setup_signal_handlers() -> nix::Result<OwnedFd> {
+    let (read, write) = unistd::pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+    // The write end stays open for as long as the handlers may run.
+    WAKEUP_PIPE.store(write.into_raw_fd(), Ordering::SeqCst);
     unsafe {
         let handler = SigHandler::Handler(exit_signal_handler);
         signal::signal(Signal::SIGABRT, handler).expect("Failed to set SIGABRT handler");
         signal::signal(Signal::SIGINT, handler).expect("Failed to set SIGINT handler");
         signal::signal(Signal::SIGTERM, handler).expect("Failed to set SIGTERM handler");
+        let handler = SigHandler::Handler(reload_signal_handler);
+        signal::signal(Signal::SIGHUP, handler).expect("Failed to set SIGHUP handler");
     }
+    Ok(read)
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let instance = Inotify::init(InitFlags::empty())?;
-    let watcher = instance.add_watch(
-        config.path.as_str(),
-        AddWatchFlags::IN_CREATE
-            | AddWatchFlags::IN_DELETE
-            | AddWatchFlags::IN_MODIFY
-            | AddWatchFlags::IN_CLOSE_WRITE
-            | AddWatchFlags::IN_MOVE_SELF,
-    )?;
-
-    setup_signal_handlers();
-
-    loop {
-        if SIGNAL_RECEIVED.load(Ordering::SeqCst) {
-            println!("Exiting gracefully...");
-            let _ = instance.rm_watch(watcher);
-            process::exit(0);
-        }
+// Empties the pipe of the signal handlers, once the signals it was written for are handled.
+fn drain(pipe: &OwnedFd) {
+    let mut buffer = [0; 64];
+    while unistd::read(pipe.as_raw_fd(), &mut buffer).is_ok_and(|read| read > 0) {}
+}
 
-        // We read from our inotify instance for events.
-        let events = instance.read_events()?;
-        let mut message: Option<String> = None;
+// The period over which `Config::rate_limit` notifications may be shown.
+const RATE_LIMIT_PERIOD: Duration = Duration::from_secs(60);
 
-        for event in events.iter() {
-            if event.mask.contains(AddWatchFlags::IN_CREATE) {
-                message = Some(String::from("File created!"));
-            }
-            if event.mask.contains(AddWatchFlags::IN_DELETE) {
-                message = Some(String::from("File deleted!"));
-            }
-            if event.mask.contains(AddWatchFlags::IN_MODIFY) {
-                message = Some(String::from("File modified!"));
-            }
-            if event.mask.contains(AddWatchFlags::IN_CLOSE_WRITE) {
-                message = Some(String::from("File witen and closed!"));
-            }
-            if event.mask.contains(AddWatchFlags::IN_MOVE_SELF) {
-                message = Some(String::from("File moved!"));
+pub fn run(mut config: Config) -> Result<(), Box<dyn Error>> {
+    let rules = config.rules.as_deref().map(Rules::load).transpose()?;
+    if !config.daemon {
+        return watch(&config, rules);
+    }
+
+    // The daemon runs from the root directory.
+    config.make_absolute()?;
+    let pidfile = config
+        .pidfile
+        .clone()
+        .unwrap_or_else(daemon::default_pidfile);
+    let _pidfile = daemon::daemonize(&path::absolute(pidfile)?)?;
+    log::to_syslog();
+    let result = watch(&config, rules);
+    if let Err(e) = &result {
+        // The standard error of the daemon goes nowhere.
+        log::error(format!("Application error: {e}"));
+    }
+    result
+}
+
+// Writes a systemd user unit for `config`.
+pub fn install(config: &Config) -> Result<(), Box<dyn Error>> {
+    let unit = daemon::install(config)?;
+    println!("Wrote {}", unit.display());
+    println!(
+        "Start it with: systemctl --user daemon-reload && systemctl --user enable --now daemonfsd"
+    );
+    Ok(())
+}
+
+fn watch(config: &Config, mut rules: Option<Rules>) -> Result<(), Box<dyn Error>> {
+    let mut watcher = Watcher::new(&config.path)?;
+    let mut debouncer = Debouncer::new(config.debounce);
+    let mut limiter = RateLimiter::new(config.rate_limit, RATE_LIMIT_PERIOD);
+    // Changes that are ready but wait for the rate limit, to be summarized together.
+    let mut held: Vec<Change> = Vec::new();
+    let watchdog = daemon::watchdog_interval();
+    let mut next_ping = Instant::now();
+
+    let wakeup = setup_signal_handlers()?;
+    notify_systemd("READY=1");
+    log::info(format!("Watching {}", config.path));
+
+    while !SIGNAL_RECEIVED.load(Ordering::SeqCst) {
+        if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+            notify_systemd("RELOADING=1");
+            // Only the rules are reloaded, and there is nothing to do without them.
+            if let Some(path) = &config.rules {
+                match Rules::load(path) {
+                    Ok(reloaded) => {
+                        rules = Some(reloaded);
+                        log::info("Reloaded the rules");
+                    }
+                    Err(e) => log::error(format!("Keeping the previous rules: {e}")),
+                }
             }
+            notify_systemd("READY=1");
+        }
+
+        let now = Instant::now();
+        if let Some(interval) = watchdog.filter(|_| now >= next_ping) {
+            notify_systemd("WATCHDOG=1");
+            next_ping = now + interval;
+        }
+
+        // Wait for events until the next pending change can be reported, or the watchdog needs
+        // to hear from us.
+        let mut deadline = debouncer.next_deadline();
+        if !held.is_empty() {
+            let allowed = limiter.next_allowed(now);
+            deadline = Some(deadline.map_or(allowed, |deadline| deadline.min(allowed)));
+        }
+        if watchdog.is_some() {
+            deadline = Some(deadline.map_or(next_ping, |deadline| deadline.min(next_ping)));
+        }
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(now));
+        let ready = watcher.wait(timeout, &wakeup)?;
+        drain(&wakeup);
+        if ready {
+            let events = watcher.read_events()?;
+            debouncer.add(event::coalesce(&watcher, &events), Instant::now());
         }
 
-        if let Some(msg) = message {
-            Notification::new()
-                .summary("DaemonFSD")
-                .body(&msg)
-                .icon("dialog-information")
-                .show()?;
+        let now = Instant::now();
+        for change in debouncer.take_ready(now) {
+            log::info(change.describe(watcher.root(), &config.file_name));
+            // Without rules, every change is in a notification.
+            let notify = match &rules {
+                Some(rules) => {
+                    let relative = change.path.strip_prefix(watcher.root()).ok();
+                    let relative = relative.filter(|relative| !relative.as_os_str().is_empty());
+                    rules.apply(&change, relative.unwrap_or(Path::new(&config.file_name)))
+                }
+                None => true,
+            };
+            if notify {
+                event::record(&mut held, change);
+            }
+        }
+        if held.is_empty() || !limiter.try_acquire(now) {
+            continue;
+        }
+        let shown = Notification::new()
+            .summary("DaemonFSD")
+            .body(&event::summarize(&held, watcher.root(), &config.file_name))
+            .icon("dialog-information")
+            .show();
+        // The notification daemon may come and go with the desktop session.
+        if let Err(e) = shown {
+            log::error(format!("Error showing a notification: {e}"));
         }
+        held.clear();
     }
+
+    log::info("Exiting gracefully...");
+    notify_systemd("STOPPING=1");
+    Ok(())
 }
 
+fn notify_systemd(state: &str) {
+    if let Err(e) = daemon::notify(state) {
+        log::error(format!("Error notifying systemd: {e}"));
+    }
+}
+
+const USAGE: &str = "Usage: daemonfsd [install] [--daemon] [--pidfile FILE] [--debounce MS] \
+                     [--rate-limit N] [--rules FILE] PATH";
+
 pub struct Config {
     pub path: String,
     pub file_name: String,
+    // How long a path must be quiet before its changes are reported.
+    pub debounce: Duration,
+    // The most notifications shown in a minute.
+    pub rate_limit: usize,
+    // A TOML file of the actions to run for changes, instead of notifying every change.
+    pub rules: Option<PathBuf>,
+    // Detach from the terminal and log to syslog.
+    pub daemon: bool,
+    pub pidfile: Option<PathBuf>,
+    // Write a systemd user unit instead of watching.
+    pub install: bool,
 }
 
 impl Config {
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
-        args.next(); // Skip the first argument which is the binary name.
+    pub fn build(args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
+        let mut args = args.skip(1).peekable(); // Skip the first argument which is the binary name.
+        let install = args.next_if(|arg| arg == "install").is_some();
 
-        let path = args.next().ok_or("Usage: deamonfsd PATH")?;
+        let mut path = None;
+        let mut debounce = Duration::from_millis(500);
+        let mut rate_limit = 10;
+        let mut rules = None;
+        let mut daemon = false;
+        let mut pidfile = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--debounce" => {
+                    let millis = args.next().and_then(|value| value.parse().ok());
+                    debounce = Duration::from_millis(
+                        millis.ok_or("--debounce takes a number of milliseconds")?,
+                    );
+                }
+                "--rate-limit" => {
+                    rate_limit = args
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .filter(|&limit| limit > 0)
+                        .ok_or("--rate-limit takes a positive number of notifications")?;
+                }
+                "--rules" => {
+                    rules = Some(PathBuf::from(args.next().ok_or("--rules takes a file")?))
+                }
+                "--daemon" => daemon = true,
+                "--pidfile" => {
+                    pidfile = Some(PathBuf::from(args.next().ok_or("--pidfile takes a file")?))
+                }
+                _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+                _ => return Err(USAGE),
+            }
+        }
+        let path = path.ok_or(USAGE)?;
 
         let path = fs::symlink_metadata(&path)
             .and_then(|metadata| {
@@ -107,6 +284,24 @@ impl Config {
         let file_name = path.split('/').collect::<Vec<&str>>();
         let file_name = file_name[file_name.len() - 1].to_string();
 
-        Ok(Config { path, file_name })
+        Ok(Config {
+            path,
+            file_name,
+            debounce,
+            rate_limit,
+            rules,
+            daemon,
+            pidfile,
+            install,
+        })
+    }
+
+    // Makes the paths absolute, so that they do not depend on the working directory.
+    fn make_absolute(&mut self) -> std::io::Result<()> {
+        self.path = fs::canonicalize(&self.path)?.display().to_string();
+        if let Some(rules) = &mut self.rules {
+            *rules = fs::canonicalize(&*rules)?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,39 @@
+use nix::libc;
+use std::env;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Whether messages go to syslog, once the daemon has detached from the terminal.
+static SYSLOG: AtomicBool = AtomicBool::new(false);
+
+pub fn to_syslog() {
+    // SAFETY: the identifier is a static string, which outlives every later call to syslog.
+    unsafe { libc::openlog(c"daemonfsd".as_ptr(), libc::LOG_PID, libc::LOG_DAEMON) };
+    SYSLOG.store(true, Ordering::SeqCst);
+}
+
+pub fn info(message: impl Display) {
+    write(libc::LOG_INFO, message);
+}
+
+pub fn error(message: impl Display) {
+    write(libc::LOG_ERR, message);
+}
+
+fn write(priority: libc::c_int, message: impl Display) {
+    if SYSLOG.load(Ordering::SeqCst) {
+        let message = message.to_string().replace('\0', "");
+        let message = CString::new(message).expect("nul bytes were removed");
+        // SAFETY: the format takes the single string argument that is passed.
+        unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+    } else if env::var_os("JOURNAL_STREAM").is_some() {
+        // Under systemd, the standard streams go to the journal, which reads the priority from
+        // this prefix.
+        println!("<{priority}>{message}");
+    } else if priority == libc::LOG_ERR {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
@@ -7,7 +7,11 @@ fn main() {
         process::exit(1);
     });
 
-    if let Err(e) = daemonfsd::run(config) {
+    let result = match config.install {
+        true => daemonfsd::install(&config),
+        false => daemonfsd::run(config),
+    };
+    if let Err(e) = result {
         eprintln!("Application error: {}", e);
         process::exit(1);
     }
@@ -0,0 +1,540 @@
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use serde_json::json;
+use std::cell::OnceCell;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::event::{Change, ChangeKind};
+use crate::log;
+
+// How long a webhook may take to accept a request and answer it.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The rules file, like:
+//
+//     [[rule]]
+//     glob = "src/**/*.rs"
+//     events = ["written", "created"]
+//     command = "cargo build"
+//
+//     [[rule]]
+//     glob = "*.md"
+//     post = "http://127.0.0.1:8080/changes"
+//     log = "/home/me/changes.jsonl"
+//     notify = true
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    // Matched against the path relative to the watched directory, or against the file name
+    // when it has no slash. Every path matches without one.
+    glob: Option<String>,
+    // The kinds of changes the rule is for, all of them by default.
+    events: Option<Vec<String>>,
+    // A shell command, run with the path in DAEMONFSD_PATH. It runs once at a time, and the
+    // changes that match while it runs start it once more, with the last of them.
+    command: Option<String>,
+    // An http:// URL that receives the change as JSON.
+    post: Option<String>,
+    // A file that the change is appended to as a line of JSON.
+    log: Option<PathBuf>,
+    #[serde(default)]
+    notify: bool,
+}
+
+enum Action {
+    Command(Job),
+    Post(Endpoint),
+    Log(PathBuf),
+    Notify,
+}
+
+struct Rule {
+    glob: Option<GlobMatcher>,
+    // Whether the glob is matched against file names only.
+    name_only: bool,
+    events: Option<Vec<String>>,
+    actions: Vec<Action>,
+}
+
+impl Rule {
+    fn build(spec: RuleSpec) -> Result<Rule, Box<dyn Error>> {
+        let glob = match &spec.glob {
+            Some(glob) => Some(
+                GlobBuilder::new(glob)
+                    .literal_separator(true)
+                    .build()?
+                    .compile_matcher(),
+            ),
+            None => None,
+        };
+        if let Some(events) = &spec.events {
+            if let Some(unknown) = events
+                .iter()
+                .find(|event| !ChangeKind::names().any(|name| name == *event))
+            {
+                return Err(format!(
+                    "unknown event `{unknown}`, expected one of {}",
+                    ChangeKind::names().collect::<Vec<_>>().join(", ")
+                )
+                .into());
+            }
+        }
+
+        let mut actions = Vec::new();
+        if let Some(command) = spec.command {
+            actions.push(Action::Command(Job::new(command)));
+        }
+        if let Some(url) = spec.post {
+            actions.push(Action::Post(Endpoint::parse(&url)?));
+        }
+        if let Some(path) = spec.log {
+            actions.push(Action::Log(path));
+        }
+        if spec.notify {
+            actions.push(Action::Notify);
+        }
+        if actions.is_empty() {
+            return Err("no action, expected command, post, log or notify".into());
+        }
+
+        Ok(Rule {
+            name_only: spec.glob.as_ref().is_some_and(|glob| !glob.contains('/')),
+            glob,
+            events: spec.events,
+            actions,
+        })
+    }
+
+    fn matches(&self, change: &Change, relative: &Path) -> bool {
+        let kind = change.kind.name();
+        if let Some(events) = &self.events {
+            if !events.iter().any(|event| event == kind) {
+                return false;
+            }
+        }
+        let Some(glob) = &self.glob else {
+            return true;
+        };
+        match self.name_only {
+            true => relative.file_name().is_some_and(|name| glob.is_match(name)),
+            false => glob.is_match(relative),
+        }
+    }
+}
+
+pub struct Rules {
+    rules: Vec<Rule>,
+    // The requests for the webhook worker, with the path of their change. The worker starts with
+    // the first request, since the rules are loaded before the daemon forks, which only keeps
+    // the thread that forks.
+    posts: OnceCell<Sender<(Endpoint, PathBuf, String)>>,
+}
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Rules, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Error reading {}: {e}", path.display()))?;
+        let file: RulesFile =
+            toml::from_str(&contents).map_err(|e| format!("Error in {}: {e}", path.display()))?;
+
+        let mut rules = Vec::with_capacity(file.rules.len());
+        for (index, spec) in file.rules.into_iter().enumerate() {
+            let rule = Rule::build(spec)
+                .map_err(|e| format!("Error in rule {} of {}: {e}", index + 1, path.display()))?;
+            rules.push(rule);
+        }
+        Ok(Rules {
+            rules,
+            posts: OnceCell::new(),
+        })
+    }
+
+    // Runs the actions of the rules that match `change`, whose path relative to the watched
+    // directory is `relative`. Returns whether it should be in a desktop notification. Actions
+    // that fail are reported, and do not stop the others.
+    pub fn apply(&self, change: &Change, relative: &Path) -> bool {
+        let mut notify = false;
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(change, relative))
+        {
+            for action in &rule.actions {
+                let result = match action {
+                    Action::Command(job) => {
+                        job.run(change);
+                        Ok(())
+                    }
+                    Action::Post(endpoint) => self
+                        .posts
+                        .get_or_init(spawn_poster)
+                        .send((endpoint.clone(), change.path.clone(), record(change)))
+                        .map_err(|_| io::Error::other("the webhook worker stopped")),
+                    Action::Log(path) => append_log(path, &record(change)),
+                    Action::Notify => {
+                        notify = true;
+                        Ok(())
+                    }
+                };
+                if let Err(e) = result {
+                    log::error(format!("Action failed for {}: {e}", change.path.display()));
+                }
+            }
+        }
+        notify
+    }
+}
+
+// The change as JSON, for webhooks and logs.
+fn record(change: &Change) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let mut record = json!({
+        "time": time,
+        "path": change.path,
+        "event": change.kind.name(),
+        "is_dir": change.is_dir,
+    });
+    if let ChangeKind::Renamed { from } = &change.kind {
+        record["from"] = json!(from);
+    }
+    record.to_string()
+}
+
+// The command of a rule, which runs in the background so that a long build does not hold back
+// the other events, one at a time so that a checkout does not start a build for each file.
+struct Job {
+    command: String,
+    state: Arc<Mutex<JobState>>,
+}
+
+#[derive(Default)]
+struct JobState {
+    running: bool,
+    // The last change that matched while the command ran, which it runs for next.
+    queued: Option<Change>,
+}
+
+impl Job {
+    fn new(command: String) -> Job {
+        Job {
+            command,
+            state: Arc::default(),
+        }
+    }
+
+    fn run(&self, change: &Change) {
+        let mut state = self.state.lock().unwrap();
+        if state.running {
+            state.queued = Some(change.clone());
+            return;
+        }
+        state.running = true;
+
+        let command = self.command.clone();
+        let state = Arc::clone(&self.state);
+        let mut change = change.clone();
+        thread::spawn(move || loop {
+            run_command(&command, &change);
+            let mut state = state.lock().unwrap();
+            match state.queued.take() {
+                Some(next) => change = next,
+                None => {
+                    state.running = false;
+                    return;
+                }
+            }
+        });
+    }
+}
+
+// Runs `command` with the shell until it exits.
+fn run_command(command: &str, change: &Change) {
+    let mut shell = Command::new("sh");
+    shell
+        .arg("-c")
+        .arg(command)
+        .env("DAEMONFSD_PATH", &change.path)
+        .env("DAEMONFSD_EVENT", change.kind.name());
+    if let ChangeKind::Renamed { from } = &change.kind {
+        shell.env("DAEMONFSD_FROM", from);
+    }
+    match shell.status() {
+        Ok(status) if !status.success() => {
+            log::error(format!("Command `{command}` failed: {status}"))
+        }
+        Ok(_) => {}
+        Err(e) => log::error(format!("Command `{command}` failed: {e}")),
+    }
+}
+
+// Starts the thread that posts to webhooks, one request after the other, so that a slow endpoint
+// does not hold back the other events. It stops once the rules are dropped, after posting what
+// they sent it.
+fn spawn_poster() -> Sender<(Endpoint, PathBuf, String)> {
+    let (sender, receiver) = mpsc::channel::<(Endpoint, PathBuf, String)>();
+    thread::spawn(move || {
+        for (endpoint, path, body) in receiver {
+            if let Err(e) = endpoint.post(&body) {
+                log::error(format!("Action failed for {}: {e}", path.display()));
+            }
+        }
+    });
+    sender
+}
+
+fn append_log(path: &Path, record: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // A single write, so that concurrent writers do not interleave lines.
+    file.write_all(format!("{record}\n").as_bytes())
+}
+
+// A plain http:// URL. Webhooks are expected to be local, so TLS is not supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+    // Without the brackets of IPv6 addresses.
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Endpoint, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("unsupported URL `{url}`, expected http://HOST[:PORT]/PATH"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        // IPv6 addresses are in brackets, like http://[::1]:8080/, since they contain colons.
+        let invalid_port = || format!("invalid port in URL `{url}`");
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| format!("unclosed bracket in URL `{url}`"))?;
+                match rest {
+                    "" => (host, None),
+                    _ => (host, Some(rest.strip_prefix(':').ok_or_else(invalid_port)?)),
+                }
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid_port())?,
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in URL `{url}`"));
+        }
+        Ok(Endpoint {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    fn post(&self, body: &str) -> io::Result<()> {
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))?;
+        let mut stream = TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT)?;
+        stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+        stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        };
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {host}:{}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.path,
+            self.port,
+            body.len(),
+        )?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "unexpected response `{}`",
+                status.trim_end()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    fn rule(spec: &str) -> Result<Rule, Box<dyn Error>> {
+        let mut file: RulesFile = toml::from_str(spec).unwrap();
+        Rule::build(file.rules.remove(0))
+    }
+
+    fn change(path: &str, kind: ChangeKind) -> Change {
+        Change {
+            path: PathBuf::from("/watched").join(path),
+            kind,
+            is_dir: false,
+        }
+    }
+
+    fn load(rules: &str) -> Result<Rules, Box<dyn Error>> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.toml");
+        fs::write(&path, rules).unwrap();
+        Rules::load(&path)
+    }
+
+    #[test]
+    fn globs_and_events() {
+        let matches =
+            |rule: &Rule, path: &str, kind| rule.matches(&change(path, kind), Path::new(path));
+        let by_name = rule("[[rule]]\nglob = '*.rs'\nnotify = true").unwrap();
+        assert!(matches(&by_name, "main.rs", ChangeKind::Created));
+        assert!(matches(&by_name, "src/lib.rs", ChangeKind::Deleted));
+        assert!(!matches(&by_name, "src/lib.rs.orig", ChangeKind::Deleted));
+
+        let by_path =
+            rule("[[rule]]\nglob = 'src/*.rs'\nevents = ['written', 'renamed']\nnotify = true")
+                .unwrap();
+        assert!(matches(&by_path, "src/lib.rs", ChangeKind::Written));
+        assert!(!matches(&by_path, "src/lib.rs", ChangeKind::Modified));
+        assert!(!matches(&by_path, "src/bin/main.rs", ChangeKind::Written));
+        assert!(!matches(&by_path, "lib.rs", ChangeKind::Written));
+
+        let every = rule("[[rule]]\nnotify = true").unwrap();
+        assert!(matches(&every, "any/thing", ChangeKind::MovedOut));
+    }
+
+    #[test]
+    fn load_errors() {
+        let error = |rules| load(rules).err().unwrap().to_string();
+        assert!(
+            error("[[rule]]\nnotify = true\n[[rule]]\nglob = '*'").contains("Error in rule 2 of")
+        );
+        assert!(error("[[rule]]\nglob = '*'").contains("no action"));
+        assert!(error("[[rule]]\nevents = ['saved']\nnotify = true")
+            .contains("unknown event `saved`, expected one of created, deleted"));
+        assert!(error("[[rule]]\npost = 'https://example.com/'").contains("unsupported URL"));
+        assert!(error("[[rule]]\nglob = '['\nnotify = true").contains("Error in rule 1"));
+        assert!(error("[[rule]]\nnotify = true\ncolor = 'red'").contains("unknown field"));
+        assert!(Rules::load(Path::new("/nonexistent/rules.toml"))
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("Error reading /nonexistent/rules.toml"));
+    }
+
+    #[test]
+    fn endpoints() {
+        let endpoint = |host: &str, port, path: &str| Endpoint {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        };
+        assert_eq!(
+            Ok(endpoint("localhost", 80, "/")),
+            Endpoint::parse("http://localhost")
+        );
+        assert_eq!(
+            Ok(endpoint("127.0.0.1", 8080, "/changes?all")),
+            Endpoint::parse("http://127.0.0.1:8080/changes?all")
+        );
+        assert_eq!(
+            Ok(endpoint("::1", 80, "/hook")),
+            Endpoint::parse("http://[::1]/hook")
+        );
+        assert_eq!(
+            Ok(endpoint("fe80::1", 9000, "/")),
+            Endpoint::parse("http://[fe80::1]:9000")
+        );
+
+        for url in [
+            "https://localhost/",
+            "http:///hook",
+            "http://localhost:http/",
+            "http://[::1/hook",
+            "http://[::1]8080/hook",
+        ] {
+            assert!(Endpoint::parse(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn commands_run_one_at_a_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output");
+        let job = Job::new(format!(
+            "echo \"$DAEMONFSD_PATH\" >> '{}'; sleep 0.2",
+            output.display()
+        ));
+
+        for path in ["a", "b", "c"] {
+            job.run(&change(path, ChangeKind::Modified));
+        }
+        // The first change runs the command, and the last one runs it once more.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while job.state.lock().unwrap().running {
+            assert!(Instant::now() < deadline, "the command did not finish");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            "/watched/a\n/watched/c\n",
+            fs::read_to_string(&output).unwrap()
+        );
+    }
+
+    #[test]
+    fn posts_in_the_background() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let rules = load(&format!("[[rule]]\npost = 'http://127.0.0.1:{port}/hook'")).unwrap();
+
+        // The request is only accepted after the change is applied, which would block otherwise.
+        assert!(!rules.apply(&change("a.txt", ChangeKind::Created), Path::new("a.txt")));
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut headers = Vec::new();
+        for line in BufReader::new(&stream).lines() {
+            let line = line.unwrap();
+            if line.is_empty() {
+                break;
+            }
+            headers.push(line);
+        }
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .unwrap();
+
+        assert_eq!("POST /hook HTTP/1.1", headers[0]);
+        assert_eq!(format!("Host: 127.0.0.1:{port}"), headers[1]);
+    }
+}
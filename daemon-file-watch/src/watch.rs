@@ -0,0 +1,298 @@
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+use std::collections::HashMap;
+use std::fs;
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// The events reported for the watched path and every directory below it.
+const WATCH_FLAGS: AddWatchFlags = AddWatchFlags::IN_CREATE
+    .union(AddWatchFlags::IN_DELETE)
+    .union(AddWatchFlags::IN_MODIFY)
+    .union(AddWatchFlags::IN_CLOSE_WRITE)
+    .union(AddWatchFlags::IN_MOVED_FROM)
+    .union(AddWatchFlags::IN_MOVED_TO)
+    .union(AddWatchFlags::IN_MOVE_SELF)
+    .union(AddWatchFlags::IN_DELETE_SELF)
+    .union(AddWatchFlags::IN_DONT_FOLLOW);
+
+// Watches a path and, when it is a directory, every directory below it, including the ones
+// created after the watcher started.
+pub struct Watcher {
+    inotify: Inotify,
+    root: PathBuf,
+    // The path of each watched file or directory.
+    paths: HashMap<WatchDescriptor, PathBuf>,
+    // Watches of deleted directories, forgotten once the events about them have been handled.
+    deleted: Vec<WatchDescriptor>,
+}
+
+impl Watcher {
+    pub fn new(root: impl Into<PathBuf>) -> nix::Result<Watcher> {
+        let mut watcher = Watcher {
+            inotify: Inotify::init(InitFlags::empty())?,
+            root: root.into(),
+            paths: HashMap::new(),
+            deleted: Vec::new(),
+        };
+        let root = watcher.root.clone();
+        watcher.add_tree(&root)?;
+        Ok(watcher)
+    }
+
+    // Waits until events can be read or `wakeup` becomes readable, for at most `timeout` if any.
+    // Returns whether events can be read, which they cannot after the timeout or a signal.
+    pub fn wait(&self, timeout: Option<Duration>, wakeup: impl AsFd) -> nix::Result<bool> {
+        // Round up, so that the deadline has passed when the wait times out.
+        let timeout = match timeout {
+            Some(timeout) => PollTimeout::try_from(timeout + Duration::from_millis(1))
+                .unwrap_or(PollTimeout::MAX),
+            None => PollTimeout::NONE,
+        };
+        let mut fds = [
+            PollFd::new(self.inotify.as_fd(), PollFlags::POLLIN),
+            PollFd::new(wakeup.as_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, timeout) {
+            Ok(_) => Ok(fds[0]
+                .revents()
+                .is_some_and(|events| events.contains(PollFlags::POLLIN))),
+            Err(Errno::EINTR) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Blocks until events are available and returns them, after updating the watches for the
+    // directories they created or deleted.
+    pub fn read_events(&mut self) -> nix::Result<Vec<InotifyEvent>> {
+        let events = self.inotify.read_events()?;
+        for wd in self.deleted.drain(..) {
+            self.paths.remove(&wd);
+        }
+        // The cookies of the directories moved within the tree by this batch.
+        let mut moved = Vec::new();
+        for event in &events {
+            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                // Events were dropped, so directories may have been created or deleted unseen.
+                self.rescan()?;
+                continue;
+            }
+            // The kernel removes the watch of a deleted directory by itself.
+            if event
+                .mask
+                .intersects(AddWatchFlags::IN_DELETE_SELF | AddWatchFlags::IN_IGNORED)
+            {
+                self.deleted.push(event.wd);
+                continue;
+            }
+            if event
+                .mask
+                .contains(AddWatchFlags::IN_MOVED_FROM | AddWatchFlags::IN_ISDIR)
+            {
+                let Some(from) = self.path(event) else {
+                    continue;
+                };
+                // A directory moved within the tree keeps its watches, which are given its new
+                // path. One moved out of the tree, or whose destination is in a later batch, is
+                // no longer watched, and is watched again if it comes back.
+                let to = events
+                    .iter()
+                    .find(|other| {
+                        other.cookie == event.cookie
+                            && other.mask.contains(AddWatchFlags::IN_MOVED_TO)
+                    })
+                    .and_then(|other| self.path(other));
+                match to {
+                    Some(to) => {
+                        self.move_tree(&from, &to);
+                        moved.push(event.cookie);
+                    }
+                    None => self.remove_tree(&from),
+                }
+                continue;
+            }
+            let created = event.mask.contains(AddWatchFlags::IN_CREATE)
+                || (event.mask.contains(AddWatchFlags::IN_MOVED_TO)
+                    && !moved.contains(&event.cookie));
+            if created && event.mask.contains(AddWatchFlags::IN_ISDIR) {
+                if let Some(path) = self.path(event) {
+                    self.add_tree(&path)?;
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // The path of the file an event is about.
+    pub fn path(&self, event: &InotifyEvent) -> Option<PathBuf> {
+        let parent = self.paths.get(&event.wd)?;
+        Some(match &event.name {
+            Some(name) => parent.join(name),
+            None => parent.clone(),
+        })
+    }
+
+    // Watches `path` and the directories below it. Files created in a new directory before its
+    // watch is added are not reported.
+    fn add_tree(&mut self, path: &Path) -> nix::Result<()> {
+        let wd = match self.inotify.add_watch(path, WATCH_FLAGS) {
+            Ok(wd) => wd,
+            // The directory was removed or became unreadable before it could be watched.
+            Err(Errno::ENOENT | Errno::EACCES) if path != self.root => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        self.paths.insert(wd, path.to_path_buf());
+
+        let Ok(entries) = fs::read_dir(path) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            // Symbolic links are not followed, so that the walk cannot loop.
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                self.add_tree(&entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Gives the watches of `from` and the directories below it their paths under `to`.
+    fn move_tree(&mut self, from: &Path, to: &Path) {
+        for path in self.paths.values_mut() {
+            if let Ok(rest) = path.strip_prefix(from) {
+                *path = match rest.as_os_str().is_empty() {
+                    true => to.to_path_buf(),
+                    false => to.join(rest),
+                };
+            }
+        }
+    }
+
+    // Removes the watches of `from` and the directories below it. They are forgotten with the
+    // next batch, so that the events about them in this one still have a path.
+    fn remove_tree(&mut self, from: &Path) {
+        for (&wd, path) in &self.paths {
+            if path.starts_with(from) {
+                let _ = self.inotify.rm_watch(wd);
+                self.deleted.push(wd);
+            }
+        }
+    }
+
+    // Walks the tree again after an overflow. Adding a watch for a watched directory returns its
+    // existing descriptor, so only the missing watches are created.
+    fn rescan(&mut self) -> nix::Result<()> {
+        let inotify = &self.inotify;
+        self.paths.retain(|&wd, path| {
+            let exists = path.exists();
+            if !exists {
+                // The directory may still exist elsewhere, having been moved out of the tree.
+                let _ = inotify.rm_watch(wd);
+            }
+            exists
+        });
+        let root = self.root.clone();
+        self.add_tree(&root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The watched paths, relative to the root.
+    fn watched(watcher: &Watcher) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = watcher
+            .paths
+            .values()
+            .map(|path| path.strip_prefix(watcher.root()).unwrap().to_path_buf())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    // The paths of the events of the next batch, relative to the root.
+    fn event_paths(watcher: &mut Watcher) -> Vec<PathBuf> {
+        let events = watcher.read_events().unwrap();
+        let root = watcher.root().to_path_buf();
+        events
+            .iter()
+            .filter_map(|event| watcher.path(event))
+            .map(|path| path.strip_prefix(&root).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn watches_the_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("a/file"), "").unwrap();
+
+        let mut watcher = Watcher::new(dir.path()).unwrap();
+        assert_eq!(paths(&["", "a", "a/b"]), watched(&watcher));
+
+        // New directories are watched as they are created.
+        fs::create_dir(dir.path().join("a/b/c")).unwrap();
+        watcher.read_events().unwrap();
+        assert_eq!(paths(&["", "a", "a/b", "a/b/c"]), watched(&watcher));
+        fs::write(dir.path().join("a/b/c/new"), "").unwrap();
+        assert!(event_paths(&mut watcher).contains(&PathBuf::from("a/b/c/new")));
+    }
+
+    #[test]
+    fn directories_moved_within_the_tree_keep_their_watches() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b/c")).unwrap();
+        let mut watcher = Watcher::new(dir.path()).unwrap();
+
+        fs::rename(dir.path().join("a/b"), dir.path().join("z")).unwrap();
+        watcher.read_events().unwrap();
+        assert_eq!(paths(&["", "a", "z", "z/c"]), watched(&watcher));
+
+        fs::write(dir.path().join("z/c/new"), "").unwrap();
+        assert!(event_paths(&mut watcher).contains(&PathBuf::from("z/c/new")));
+    }
+
+    #[test]
+    fn directories_moved_out_of_the_tree_are_forgotten() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        let mut watcher = Watcher::new(dir.path()).unwrap();
+
+        fs::rename(dir.path().join("a"), outside.path().join("a")).unwrap();
+        watcher.read_events().unwrap();
+        // The removed watches report that they are ignored in the next batch.
+        watcher.read_events().unwrap();
+        assert_eq!(paths(&[""]), watched(&watcher));
+
+        fs::write(outside.path().join("a/b/new"), "").unwrap();
+        fs::write(dir.path().join("inside"), "").unwrap();
+        assert!(event_paths(&mut watcher)
+            .iter()
+            .all(|path| path == Path::new("inside")));
+    }
+
+    #[test]
+    fn rescan_catches_up_with_the_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        let mut watcher = Watcher::new(dir.path()).unwrap();
+
+        // Changes whose events were lost in an overflow.
+        fs::rename(dir.path().join("a"), outside.path().join("a")).unwrap();
+        fs::create_dir_all(dir.path().join("n/m")).unwrap();
+        watcher.rescan().unwrap();
+        assert_eq!(paths(&["", "n", "n/m"]), watched(&watcher));
+    }
+}
@@ -0,0 +1,72 @@
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::TcpListener;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The daemon forks after loading the rules, and must still post the changes they match.
+#[test]
+fn daemon_posts_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let watched = dir.path().join("watched");
+    fs::create_dir(&watched).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let rules = dir.path().join("rules.toml");
+    fs::write(
+        &rules,
+        format!("[[rule]]\npost = 'http://127.0.0.1:{port}/hook'\n"),
+    )
+    .unwrap();
+    let pidfile = dir.path().join("daemonfsd.pid");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_daemonfsd"))
+        .arg("--daemon")
+        .arg("--pidfile")
+        .arg(&pidfile)
+        .args(["--debounce", "10", "--rules"])
+        .arg(&rules)
+        .arg(&watched)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The pidfile is written once the daemon watches the directory.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let pid = loop {
+        if let Some(pid) = fs::read_to_string(&pidfile)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<i32>().ok())
+        {
+            break pid;
+        }
+        assert!(Instant::now() < deadline, "the daemon did not start");
+        thread::sleep(Duration::from_millis(10));
+    };
+    thread::sleep(Duration::from_millis(100));
+    fs::write(watched.join("file"), "contents").unwrap();
+
+    let accepted = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break Some(stream),
+            Err(e) if e.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10))
+            }
+            Err(_) => break None,
+        }
+    };
+    let _ = signal::kill(Pid::from_raw(pid), Signal::SIGTERM);
+
+    let mut stream = accepted.expect("the daemon did not post the change");
+    stream.set_nonblocking(false).unwrap();
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request).unwrap();
+    stream
+        .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+        .unwrap();
+    assert_eq!("POST /hook HTTP/1.1\r\n", request);
+}
@@ -0,0 +1,84 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::hint::black_box;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mygrep::Config;
+
+const DIRS: usize = 50;
+const FILES_PER_DIR: usize = 40;
+const LINES_PER_FILE: usize = 500;
+
+/// Writes a tree of `DIRS * FILES_PER_DIR` files, where one line in a hundred contains "needle".
+fn generate_tree(root: &Path) -> u64 {
+    let mut bytes = 0;
+    for dir in 0..DIRS {
+        let dir_path = root.join(format!("dir{dir}"));
+        fs::create_dir_all(&dir_path).unwrap();
+        for file in 0..FILES_PER_DIR {
+            let mut contents = String::new();
+            for line in 0..LINES_PER_FILE {
+                let word = if line % 100 == 0 { "needle" } else { "hay" };
+                let _ = writeln!(
+                    contents,
+                    "{dir}/{file}/{line}: some {word} in a line of generated text"
+                );
+            }
+            bytes += contents.len() as u64;
+            fs::write(dir_path.join(format!("file{file}.txt")), contents).unwrap();
+        }
+    }
+    bytes
+}
+
+fn config(root: &Path, threads: usize, regex: bool) -> Config {
+    let mut args = vec![String::from("mygrep"), String::from("-r")];
+    args.push(format!("-j{threads}"));
+    match regex {
+        true => args.extend([String::from("-E"), String::from(r"ne+dle\b")]),
+        false => args.push(String::from("needle")),
+    }
+    args.push(root.display().to_string());
+    Config::build(args.into_iter()).unwrap()
+}
+
+fn search_tree(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let bytes = generate_tree(dir.path());
+    let mut thread_counts = vec![
+        1,
+        2,
+        4,
+        thread::available_parallelism().map_or(1, |n| n.get()),
+    ];
+    thread_counts.sort();
+    thread_counts.dedup();
+
+    let mut group = c.benchmark_group("search_tree");
+    group.throughput(Throughput::Bytes(bytes));
+    group.sample_size(20);
+    for regex in [false, true] {
+        let mode = if regex { "regex" } else { "literal" };
+        for &threads in &thread_counts {
+            let config = config(dir.path(), threads, regex);
+            group.bench_with_input(BenchmarkId::new(mode, threads), &config, |b, config| {
+                b.iter(|| {
+                    let matches = AtomicUsize::new(0);
+                    mygrep::search_paths(config, |output| {
+                        let lines = output.iter().filter(|&&b| b == b'\n').count();
+                        matches.fetch_add(lines, Ordering::Relaxed);
+                    })
+                    .unwrap();
+                    black_box(matches.into_inner())
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, search_tree);
+criterion_main!(benches);
@@ -0,0 +1,184 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
+use std::path::Path;
+
+use crate::searcher::Searcher;
+use crate::{search, Config};
+
+/// A compression format that `-z` decompresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Recognizes the format by the extension of `name`, or else by the magic bytes at the start
+    /// of `head`.
+    fn detect(name: &str, head: &[u8]) -> Option<Compression> {
+        let extension = name.rsplit_once('.').map(|(_, extension)| extension);
+        match extension {
+            Some("gz" | "tgz") => Some(Compression::Gzip),
+            Some("zst" | "tzst") => Some(Compression::Zstd),
+            Some("bz2" | "tbz2") => Some(Compression::Bzip2),
+            _ if head.starts_with(b"\x1f\x8b") => Some(Compression::Gzip),
+            _ if head.starts_with(b"\x28\xb5\x2f\xfd") => Some(Compression::Zstd),
+            // The magic is followed by the block size, from 1 to 9.
+            _ if head.starts_with(b"BZh") && matches!(head.get(3), Some(b'1'..=b'9')) => {
+                Some(Compression::Bzip2)
+            }
+            _ => None,
+        }
+    }
+
+    fn decoder<'r>(self, reader: impl BufRead + 'r) -> io::Result<Box<dyn Read + 'r>> {
+        // Rotated logs are sometimes concatenations of several compressed streams.
+        Ok(match self {
+            Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+            Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+        })
+    }
+}
+
+/// Whether the decompressed contents named `name` are a tar archive, by extension or by the
+/// `ustar` magic in the first header.
+fn is_tar(name: &str, head: &[u8]) -> bool {
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    let name = name.strip_suffix(".zst").unwrap_or(name);
+    let name = name.strip_suffix(".bz2").unwrap_or(name);
+    [".tar", ".tgz", ".tzst", ".tbz2"]
+        .iter()
+        .any(|extension| name.ends_with(extension))
+        || head.get(257..262) == Some(b"ustar")
+}
+
+fn is_zip(name: &str, head: &[u8]) -> bool {
+    name.ends_with(".zip") || head.starts_with(b"PK\x03\x04")
+}
+
+/// Searches the file at `path` when it is compressed or an archive, and returns `None` for any
+/// other file. Files inside archives are named `archive:inner/path`, and lines in them are always
+/// prefixed with that name.
+pub(crate) fn search_file(
+    config: &Config,
+    searcher: &Searcher,
+    path: &Path,
+    with_file_name: bool,
+    out: &mut dyn Write,
+) -> io::Result<Option<usize>> {
+    let name = path.display().to_string();
+    let mut file = BufReader::new(File::open(path)?);
+    let head = file.fill_buf()?;
+
+    let compression = Compression::detect(&name, head);
+    if compression.is_none() && is_zip(&name, head) {
+        return search_zip(config, searcher, &name, file.into_inner(), out).map(Some);
+    }
+    if compression.is_none() && !is_tar(&name, head) {
+        return Ok(None);
+    }
+
+    search_reader(config, searcher, &name, with_file_name, file, out).map(Some)
+}
+
+/// Searches `reader`, decompressing it when its magic bytes are recognized. This is how the
+/// standard input is searched with `-z`.
+pub(crate) fn search_reader(
+    config: &Config,
+    searcher: &Searcher,
+    name: &str,
+    with_file_name: bool,
+    mut reader: impl BufRead,
+    out: &mut dyn Write,
+) -> io::Result<usize> {
+    let mut reader: Box<dyn BufRead> = match Compression::detect(name, reader.fill_buf()?) {
+        Some(compression) => Box::new(BufReader::new(compression.decoder(reader)?)),
+        None => Box::new(reader),
+    };
+    if is_tar(name, reader.fill_buf()?) {
+        return search_tar(config, searcher, name, reader, out);
+    }
+    search::search_reader(config, searcher, name, with_file_name, reader, out)
+}
+
+fn search_tar(
+    config: &Config,
+    searcher: &Searcher,
+    name: &str,
+    reader: impl Read,
+    out: &mut dyn Write,
+) -> io::Result<usize> {
+    let mut archive = tar::Archive::new(reader);
+    let mut selected = 0;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = format!("{name}:{}", entry.path()?.display());
+        selected +=
+            search::search_reader(config, searcher, &name, true, BufReader::new(entry), out)?;
+        if config.quiet && selected > 0 {
+            break;
+        }
+    }
+    Ok(selected)
+}
+
+fn search_zip(
+    config: &Config,
+    searcher: &Searcher,
+    name: &str,
+    reader: impl Read + Seek,
+    out: &mut dyn Write,
+) -> io::Result<usize> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut selected = 0;
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let name = format!("{name}:{}", entry.name());
+        selected +=
+            search::search_reader(config, searcher, &name, true, BufReader::new(entry), out)?;
+        if config.quiet && selected > 0 {
+            break;
+        }
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_compression() {
+        assert_eq!(
+            Some(Compression::Gzip),
+            Compression::detect("app.log.gz", b"")
+        );
+        assert_eq!(
+            Some(Compression::Zstd),
+            Compression::detect("app.tzst", b"")
+        );
+        assert_eq!(
+            Some(Compression::Bzip2),
+            Compression::detect("app.log.1", b"BZh91AY&SY")
+        );
+        assert_eq!(
+            Some(Compression::Gzip),
+            Compression::detect("-", b"\x1f\x8b\x08")
+        );
+        assert_eq!(None, Compression::detect("app.log", b"plain text"));
+        assert_eq!(None, Compression::detect("app.log", b"BZh, said the bee"));
+
+        assert!(is_tar("logs.tar.zst", b""));
+        assert!(is_tar("logs.tgz", b""));
+        assert!(!is_tar("logs.gz", b""));
+        assert!(is_zip("logs.bin", b"PK\x03\x04"));
+    }
+}
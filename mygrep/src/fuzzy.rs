@@ -0,0 +1,331 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Range;
+
+use crate::matcher::{Match, Matcher};
+
+/// The longest pattern that fits in the bit vectors of the matcher.
+pub const MAX_PATTERN_LEN: usize = u64::BITS as usize;
+
+/// A character of a line with its byte range. Invalid bytes are `None`.
+type Symbol = (Range<usize>, Option<char>);
+
+thread_local! {
+    /// The symbols of the line being searched by `find_match_at`, which needs to go back over
+    /// them. The buffer is kept between lines, so that searching does not allocate for each.
+    static SYMBOLS: RefCell<Vec<Symbol>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Matches the windows of a line within a Levenshtein distance of a pattern, the number of
+/// characters to insert, delete or substitute to turn one into the other.
+///
+/// Lines are scanned with Myers' bit-parallel algorithm, which updates a whole column of the edit
+/// distance matrix with a few operations on 64-bit words. Invalid UTF-8 bytes count as characters
+/// that match no character of the pattern.
+pub struct FuzzyMatcher {
+    len: usize,
+    max_distance: usize,
+    ignore_case: bool,
+    forward: PatternMasks,
+    /// The masks of the reversed pattern, to find where a window starts from where it ends.
+    backward: PatternMasks,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FuzzyError {
+    TooLong,
+    /// The distance lets the empty window match every line.
+    DistanceTooLarge,
+}
+
+impl fmt::Display for FuzzyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FuzzyError::TooLong => write!(
+                f,
+                "fuzzy patterns are limited to {MAX_PATTERN_LEN} characters"
+            ),
+            FuzzyError::DistanceTooLarge => {
+                write!(f, "the fuzzy distance must be less than the pattern length")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FuzzyError {}
+
+impl FuzzyMatcher {
+    pub fn new(
+        pattern: &str,
+        max_distance: usize,
+        ignore_case: bool,
+    ) -> Result<FuzzyMatcher, FuzzyError> {
+        let chars: Vec<char> = pattern.chars().map(|c| fold(c, ignore_case)).collect();
+        if chars.len() > MAX_PATTERN_LEN {
+            return Err(FuzzyError::TooLong);
+        }
+        if max_distance >= chars.len() {
+            return Err(FuzzyError::DistanceTooLarge);
+        }
+
+        Ok(FuzzyMatcher {
+            len: chars.len(),
+            max_distance,
+            ignore_case,
+            forward: PatternMasks::new(chars.iter().copied()),
+            backward: PatternMasks::new(chars.iter().rev().copied()),
+        })
+    }
+
+    /// The characters of `haystack` from `start`, with their byte ranges.
+    fn symbols<'h>(&self, haystack: &'h [u8], start: usize) -> impl Iterator<Item = Symbol> + 'h {
+        let ignore_case = self.ignore_case;
+        haystack[start..]
+            .utf8_chunks()
+            .scan(start, move |offset, chunk| {
+                let valid_start = *offset;
+                let invalid_start = valid_start + chunk.valid().len();
+                *offset = invalid_start + chunk.invalid().len();
+                let valid = chunk.valid().char_indices().map(move |(index, c)| {
+                    let start = valid_start + index;
+                    (start..start + c.len_utf8(), Some(fold(c, ignore_case)))
+                });
+                let invalid = (invalid_start..*offset).map(|offset| (offset..offset + 1, None));
+                Some(valid.chain(invalid))
+            })
+            .flatten()
+    }
+
+    /// Finds the index of the last symbol of the leftmost window with the smallest distance, and
+    /// that distance. With `first`, stops at the first window within the maximum distance.
+    ///
+    /// A window that ends one character later with the same distance is preferred, so that "caf"
+    /// does not win over "cafe" for "café".
+    fn best_end(
+        &self,
+        symbols: impl Iterator<Item = Option<char>>,
+        first: bool,
+    ) -> Option<(usize, usize)> {
+        let mut columns = Columns::new(self.len);
+        let mut best: Option<(usize, usize)> = None;
+        for (index, symbol) in symbols.enumerate() {
+            // Windows can start anywhere, so the first row of the matrix stays at 0.
+            let distance = columns.advance(self.forward.get(symbol), false);
+            if distance > self.max_distance {
+                continue;
+            }
+            match best {
+                Some((end, best)) if distance > best || (distance == best && end + 1 < index) => {}
+                _ => best = Some((index, distance)),
+            }
+            if first || distance == 0 {
+                break;
+            }
+        }
+        best
+    }
+
+    /// Finds the index of the first symbol of the shortest window that ends at `end` with
+    /// `distance`, by matching the reversed pattern backwards from `end`.
+    fn window_start(&self, symbols: &[Symbol], end: usize, distance: usize) -> usize {
+        let mut columns = Columns::new(self.len);
+        for index in (0..=end).rev() {
+            // The window is anchored at `end`, so the first row counts the skipped characters.
+            if columns.advance(self.backward.get(symbols[index].1), true) == distance {
+                return index;
+            }
+        }
+        0
+    }
+}
+
+impl Matcher for FuzzyMatcher {
+    fn find_match_at(&self, haystack: &[u8], start: usize) -> Option<Match> {
+        SYMBOLS.with_borrow_mut(|symbols| {
+            symbols.clear();
+            symbols.extend(self.symbols(haystack, start));
+            let (end, distance) = self.best_end(symbols.iter().map(|(_, c)| *c), false)?;
+            let start = self.window_start(symbols, end, distance);
+            Some(Match {
+                pattern: 0,
+                range: symbols[start].0.start..symbols[end].0.end,
+            })
+        })
+    }
+
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        let symbols = self.symbols(haystack, 0).map(|(_, c)| c);
+        self.best_end(symbols, true).is_some()
+    }
+
+    /// The smallest distance between the pattern and a window of `haystack`.
+    fn score(&self, haystack: &[u8]) -> Option<usize> {
+        let symbols = self.symbols(haystack, 0).map(|(_, c)| c);
+        self.best_end(symbols, false).map(|(_, distance)| distance)
+    }
+}
+
+fn fold(c: char, ignore_case: bool) -> char {
+    match ignore_case {
+        true => c.to_lowercase().next().unwrap_or(c),
+        false => c,
+    }
+}
+
+/// For each character, the positions where it occurs in the pattern, as a bit mask.
+struct PatternMasks {
+    ascii: [u64; 128],
+    other: Vec<(char, u64)>,
+}
+
+impl PatternMasks {
+    fn new(pattern: impl Iterator<Item = char>) -> PatternMasks {
+        let mut masks = PatternMasks {
+            ascii: [0; 128],
+            other: Vec::new(),
+        };
+        for (position, c) in pattern.enumerate() {
+            let bit = 1 << position;
+            match c.is_ascii() {
+                true => masks.ascii[c as usize] |= bit,
+                false => match masks.other.iter_mut().find(|(other, _)| *other == c) {
+                    Some((_, mask)) => *mask |= bit,
+                    None => masks.other.push((c, bit)),
+                },
+            }
+        }
+        masks
+    }
+
+    fn get(&self, c: Option<char>) -> u64 {
+        match c {
+            Some(c) if c.is_ascii() => self.ascii[c as usize],
+            Some(c) => self
+                .other
+                .iter()
+                .find(|(other, _)| *other == c)
+                .map_or(0, |(_, mask)| *mask),
+            None => 0,
+        }
+    }
+}
+
+/// The vertical deltas of the current column of the edit distance matrix, each bit for a row,
+/// and the distance in its last row.
+struct Columns {
+    positive: u64,
+    negative: u64,
+    last_row: u64,
+    distance: usize,
+}
+
+impl Columns {
+    fn new(len: usize) -> Columns {
+        Columns {
+            positive: !0,
+            negative: 0,
+            last_row: 1 << (len - 1),
+            distance: len,
+        }
+    }
+
+    /// Moves to the next column for a text character that occurs in the pattern at the positions
+    /// in `mask`, and returns the distance in the last row.
+    fn advance(&mut self, mask: u64, anchored: bool) -> usize {
+        let vertical = mask | self.negative;
+        let horizontal =
+            ((mask & self.positive).wrapping_add(self.positive) ^ self.positive) | mask;
+        let mut positive = self.negative | !(horizontal | self.positive);
+        let mut negative = self.positive & horizontal;
+
+        if positive & self.last_row != 0 {
+            self.distance += 1;
+        } else if negative & self.last_row != 0 {
+            self.distance -= 1;
+        }
+
+        positive = (positive << 1) | anchored as u64;
+        negative <<= 1;
+        self.positive = negative | !(vertical | positive);
+        self.negative = positive & vertical;
+        self.distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The smallest edit distance between `pattern` and a substring of `text`, by dynamic
+    /// programming.
+    fn naive_distance(pattern: &[u8], text: &[u8]) -> usize {
+        let mut column: Vec<usize> = (0..=pattern.len()).collect();
+        let mut best = pattern.len();
+        for &t in text {
+            let mut diagonal = 0;
+            column[0] = 0;
+            for (row, &p) in pattern.iter().enumerate() {
+                let substituted = diagonal + (p != t) as usize;
+                diagonal = column[row + 1];
+                column[row + 1] = substituted.min(column[row] + 1).min(diagonal + 1);
+            }
+            best = best.min(column[pattern.len()]);
+        }
+        best
+    }
+
+    #[test]
+    fn distances_match_dynamic_programming() {
+        // A small linear congruential generator keeps the test deterministic.
+        let mut state = 7u32;
+        let mut next = |modulo: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) % modulo
+        };
+        for _ in 0..500 {
+            let pattern: Vec<u8> = (0..1 + next(8)).map(|_| b'a' + next(3) as u8).collect();
+            let text: Vec<u8> = (0..next(20)).map(|_| b'a' + next(3) as u8).collect();
+            let expected = naive_distance(&pattern, &text);
+            let pattern = std::str::from_utf8(&pattern).unwrap();
+
+            for max_distance in 0..pattern.len() {
+                let matcher = FuzzyMatcher::new(pattern, max_distance, false).unwrap();
+                let score = (expected <= max_distance).then_some(expected);
+                assert_eq!(score, matcher.score(&text), "{pattern} in {text:?}");
+                assert_eq!(score.is_some(), matcher.is_match(&text));
+            }
+        }
+    }
+
+    #[test]
+    fn best_window() {
+        let matcher = FuzzyMatcher::new("necessary", 2, false).unwrap();
+        let line = b"it is neccesary, not necesary";
+        assert_eq!(Some(21..29), matcher.find(line));
+        assert_eq!(Some(1), matcher.score(line));
+        assert_eq!(Some(6..15), matcher.find(b"it is neccesary"));
+        assert!(!matcher.is_match(b"it is needless"));
+    }
+
+    #[test]
+    fn characters_and_case() {
+        let matcher = FuzzyMatcher::new("Café", 1, true).unwrap();
+        let line = "un cafe noir".as_bytes();
+        assert_eq!(Some(3..7), matcher.find(line));
+        assert_eq!(Some(0), matcher.score("CAFÉ".as_bytes()));
+        // An invalid byte is one substituted character.
+        assert_eq!(Some(3..7), matcher.find(b"un caf\xff noir"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert_eq!(
+            Some(FuzzyError::TooLong),
+            FuzzyMatcher::new(&"a".repeat(65), 1, false).err()
+        );
+        assert_eq!(
+            Some(FuzzyError::DistanceTooLarge),
+            FuzzyMatcher::new("ab", 2, false).err()
+        );
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use encoding_rs::Encoding;
+use tempfile::NamedTempFile;
+
+use crate::walk::{self, WalkOptions};
+use crate::Config;
+
+/// The name of the index file, in the indexed directory.
+pub const INDEX_FILE: &str = ".mygrep-index";
+
+const MAGIC: &[u8; 8] = b"MYGREPIX";
+const VERSION: u32 = 2;
+
+/// A trigram index of the files under a directory, which tells which files may contain a fixed
+/// string without reading them.
+///
+/// For each file, the index keeps the set of its trigrams, the sequences of three bytes in it,
+/// with ASCII letters lowercased. A file can only contain a string if it contains all of its
+/// trigrams. Files are identified by their path relative to the directory, their modification
+/// time and their size, so a file that changed since it was indexed is always searched.
+pub struct Index {
+    root: PathBuf,
+    files: HashMap<String, IndexedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IndexedFile {
+    stamp: Stamp,
+    /// Sorted trigrams, each packed in the low 24 bits.
+    trigrams: Vec<u32>,
+}
+
+/// When a file was last modified, and its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    seconds: u64,
+    nanos: u32,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(metadata: &fs::Metadata) -> Option<Stamp> {
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            seconds: modified.as_secs(),
+            nanos: modified.subsec_nanos(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// What `Index::update` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UpdateStats {
+    /// The number of files in the index.
+    pub files: usize,
+    /// The number of files that were new or changed, and read.
+    pub reindexed: usize,
+    /// The number of files that disappeared.
+    pub removed: usize,
+}
+
+impl Index {
+    /// An empty index of `root`.
+    pub fn new(root: &Path) -> Index {
+        Index {
+            root: root.to_path_buf(),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Loads the index of `root`, or returns `None` when it has none.
+    pub fn load(root: &Path) -> io::Result<Option<Index>> {
+        let mut contents = Vec::new();
+        match File::open(root.join(INDEX_FILE)) {
+            Ok(mut file) => file.read_to_end(&mut contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut index = Index::new(root);
+        let mut reader = Reader(&contents);
+        if reader.take(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+            return Err(invalid_index());
+        }
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            let path =
+                String::from_utf8(reader.take(len)?.to_vec()).map_err(|_| invalid_index())?;
+            let stamp = Stamp {
+                seconds: reader.u64()?,
+                nanos: reader.u32()?,
+                len: reader.u64()?,
+            };
+            let count = reader.u32()? as usize;
+            let trigrams = (0..count)
+                .map(|_| reader.u32())
+                .collect::<io::Result<_>>()?;
+            index.files.insert(path, IndexedFile { stamp, trigrams });
+        }
+        Ok(Some(index))
+    }
+
+    /// Writes the index into its directory, replacing the previous one at once.
+    pub fn save(&self) -> io::Result<()> {
+        let mut file = NamedTempFile::new_in(&self.root)?;
+        let mut out = BufWriter::new(file.as_file_mut());
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(self.files.len() as u32).to_le_bytes())?;
+        let mut paths: Vec<_> = self.files.keys().collect();
+        paths.sort();
+        for path in paths {
+            let file = &self.files[path];
+            out.write_all(&(path.len() as u32).to_le_bytes())?;
+            out.write_all(path.as_bytes())?;
+            out.write_all(&file.stamp.seconds.to_le_bytes())?;
+            out.write_all(&file.stamp.nanos.to_le_bytes())?;
+            out.write_all(&file.stamp.len.to_le_bytes())?;
+            out.write_all(&(file.trigrams.len() as u32).to_le_bytes())?;
+            for trigram in &file.trigrams {
+                out.write_all(&trigram.to_le_bytes())?;
+            }
+        }
+        out.flush()?;
+        drop(out);
+        file.persist(self.root.join(INDEX_FILE))
+            .map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Brings the index up to date with the files under its directory, walked with `threads`
+    /// threads. Only the files that are new or whose modification time or size changed are read.
+    pub fn update(&mut self, threads: usize) -> Result<UpdateStats, Box<dyn std::error::Error>> {
+        let options = WalkOptions {
+            recursive: true,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            threads,
+        };
+        let files = Mutex::new(HashMap::new());
+        let reindexed = Mutex::new(0);
+        let errors = Mutex::new(Vec::new());
+
+        let root = self.root.display().to_string();
+        walk::visit(&[root], &options, |path| {
+            let indexed = path
+                .map_err(io::Error::other)
+                .and_then(|path| self.index_file(path));
+            match indexed {
+                Ok(Some((path, file, read))) => {
+                    *reindexed.lock().unwrap() += read as usize;
+                    files.lock().unwrap().insert(path, file);
+                }
+                Ok(None) => {}
+                Err(e) => errors.lock().unwrap().push(e.to_string()),
+            }
+            true
+        })?;
+
+        if let Some(error) = errors.into_inner().unwrap().into_iter().next() {
+            return Err(error.into());
+        }
+        let files = files.into_inner().unwrap();
+        let removed = self
+            .files
+            .keys()
+            .filter(|path| !files.contains_key(*path))
+            .count();
+        self.files = files;
+        Ok(UpdateStats {
+            files: self.files.len(),
+            reindexed: reindexed.into_inner().unwrap(),
+            removed,
+        })
+    }
+
+    /// Indexes the file at `path`, reusing its previous entry when it did not change. Returns its
+    /// relative path, its entry and whether it was read.
+    fn index_file(&self, path: &Path) -> io::Result<Option<(String, IndexedFile, bool)>> {
+        let Some(relative) = self.relative(path) else {
+            return Ok(None);
+        };
+        if relative == INDEX_FILE {
+            return Ok(None);
+        }
+        let Some(stamp) = Stamp::of(&fs::metadata(path)?) else {
+            return Ok(None);
+        };
+        if let Some(file) = self.files.get(&relative).filter(|file| file.stamp == stamp) {
+            return Ok(Some((relative, file.clone(), false)));
+        }
+
+        // Searches decode text that starts with a byte order mark, so its trigrams are those of
+        // the decoded text.
+        let contents = fs::read(path)?;
+        let trigrams = match Encoding::for_bom(&contents) {
+            Some((encoding, bom)) => {
+                let (text, _) = encoding.decode_without_bom_handling(&contents[bom..]);
+                trigrams(text.as_bytes())
+            }
+            None => trigrams(&contents),
+        };
+        Ok(Some((relative, IndexedFile { stamp, trigrams }, true)))
+    }
+
+    /// The path of a walked file relative to the indexed directory, when it is valid UTF-8.
+    fn relative(&self, path: &Path) -> Option<String> {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            // Walking the current directory reports paths relative to it.
+            Err(_) if self.root == Path::new(".") => path,
+            Err(_) => return None,
+        };
+        relative.to_str().map(str::to_string)
+    }
+
+    /// Whether the file at `path`, which has `metadata`, may contain a match for `query`. Files
+    /// that are not indexed or changed since they were may always contain one.
+    pub fn may_match(&self, path: &Path, metadata: &fs::Metadata, query: &Query) -> bool {
+        let indexed = self
+            .relative(path)
+            .and_then(|relative| self.files.get(&relative))
+            .filter(|file| Stamp::of(metadata) == Some(file.stamp));
+        let Some(file) = indexed else {
+            return true;
+        };
+        query.alternatives.iter().any(|trigrams| {
+            trigrams
+                .iter()
+                .all(|trigram| file.trigrams.binary_search(trigram).is_ok())
+        })
+    }
+}
+
+/// The trigrams that a file must contain to match the patterns of a search.
+pub struct Query {
+    /// A file may match when it has all the trigrams of one of the alternatives.
+    alternatives: Vec<Vec<u32>>,
+}
+
+impl Query {
+    /// The query for the search of `config`, or `None` when the index cannot tell which files
+    /// may match it.
+    pub fn new(config: &Config) -> Option<Query> {
+        // Decoded text has other trigrams than the indexed bytes.
+        let raw = !(config.search_zip || config.encoding.is_some());
+        let exact = !(config.regex || config.fuzzy.is_some()) && raw;
+        // Without any match, inverted searches select lines of every file.
+        let selects_matches = !(config.invert_match || config.files_without_match);
+        // The index only folds ASCII letters.
+        let folds = !config.ignore_case || config.patterns.iter().all(|p| p.is_ascii());
+        if !(exact && selects_matches && folds) {
+            return None;
+        }
+
+        let alternatives: Vec<_> = config
+            .patterns
+            .iter()
+            .map(|pattern| trigrams(pattern.as_bytes()))
+            .collect();
+        // Patterns shorter than a trigram may match anywhere.
+        if alternatives.iter().any(Vec::is_empty) {
+            return None;
+        }
+        Some(Query { alternatives })
+    }
+}
+
+/// The sorted distinct trigrams of `bytes`, with ASCII letters lowercased.
+fn trigrams(bytes: &[u8]) -> Vec<u32> {
+    let mut trigrams: Vec<u32> = bytes
+        .windows(3)
+        .map(|window| {
+            let [a, b, c] = [0, 1, 2].map(|i| window[i].to_ascii_lowercase() as u32);
+            a << 16 | b << 8 | c
+        })
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+fn invalid_index() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "invalid index, rebuild it with `mygrep index build`",
+    )
+}
+
+/// Reads the little-endian fields of an index file.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid_index());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(args: &[&str]) -> Option<Query> {
+        let args = ["mygrep"].iter().chain(args).map(|arg| arg.to_string());
+        Query::new(&Config::build(args).unwrap())
+    }
+
+    #[test]
+    fn trigrams_fold_ascii() {
+        assert_eq!(trigrams(b"abcd"), trigrams(b"aBCd"));
+        assert_eq!(2, trigrams(b"abcd").len());
+        assert!(trigrams(b"ab").is_empty());
+    }
+
+    #[test]
+    fn queries() {
+        assert_eq!(1, query(&["needle"]).unwrap().alternatives.len());
+        assert_eq!(
+            2,
+            query(&["-e", "needle", "-e", "hay"])
+                .unwrap()
+                .alternatives
+                .len()
+        );
+        assert!(query(&["-i", "NEEDLE"]).is_some());
+
+        assert!(query(&["ne"]).is_none());
+        assert!(query(&["-e", "needle", "-e", "ha"]).is_none());
+        assert!(query(&["-E", "needle"]).is_none());
+        assert!(query(&["-v", "needle"]).is_none());
+        assert!(query(&["-i", "straße"]).is_none());
+        assert!(query(&["--encoding", "latin1", "needle"]).is_none());
+    }
+
+    #[test]
+    fn incremental_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a.txt"), "a needle\n").unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/b.txt"), "some hay\n").unwrap();
+
+        let mut index = Index::new(root);
+        let stats = index.update(1).unwrap();
+        assert_eq!(
+            UpdateStats {
+                files: 2,
+                reindexed: 2,
+                removed: 0
+            },
+            stats
+        );
+        index.save().unwrap();
+
+        let mut index = Index::load(root).unwrap().unwrap();
+        let query = query(&["NEEDLE"]).unwrap();
+        let a = root.join("a.txt");
+        let b = root.join("sub/b.txt");
+        assert!(index.may_match(&a, &fs::metadata(&a).unwrap(), &query));
+        assert!(!index.may_match(&b, &fs::metadata(&b).unwrap(), &query));
+
+        // A file that changed since it was indexed may always match.
+        fs::write(&b, "some hay and a needle\n").unwrap();
+        assert!(index.may_match(&b, &fs::metadata(&b).unwrap(), &query));
+
+        fs::remove_file(&a).unwrap();
+        let stats = index.update(2).unwrap();
+        assert_eq!(
+            UpdateStats {
+                files: 1,
+                reindexed: 1,
+                removed: 1
+            },
+            stats
+        );
+        assert!(index.may_match(&b, &fs::metadata(&b).unwrap(), &query));
+    }
+
+    #[test]
+    fn invalid_index_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Index::load(dir.path()).unwrap().is_none());
+        fs::write(dir.path().join(INDEX_FILE), b"MYGREPIX\x01\x00\x00\x00\x05").unwrap();
+        let error = Index::load(dir.path()).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+}
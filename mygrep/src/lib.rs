@@ -1,67 +1,681 @@
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use encoding_rs::Encoding;
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-    } else {
-        search(&config.query, &contents)
+mod compressed;
+pub mod fuzzy;
+pub mod index;
+pub mod matcher;
+mod printer;
+mod replace;
+mod search;
+pub mod searcher;
+pub mod walk;
+
+use fuzzy::FuzzyMatcher;
+use index::{Index, Query, INDEX_FILE};
+use matcher::{LiteralMatcher, LiteralSetMatcher, Matcher, RegexMatcher};
+use searcher::{BinaryDetection, Searcher, SearcherBuilder};
+use walk::WalkOptions;
+
+pub fn run(config: Config) -> Result<Summary, Box<dyn Error>> {
+    let stdout = io::stdout();
+    search_paths(&config, |output| {
+        // The reader may go away early, e.g. when piped into `head`.
+        let _ = stdout.lock().write_all(output);
+    })
+}
+
+/// The path that stands for the standard input.
+pub const STDIN_PATH: &str = "-";
+
+/// What happened during a search, which decides the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    /// Whether any line was selected.
+    pub matched: bool,
+    /// Whether some file could not be searched.
+    pub had_errors: bool,
+}
+
+/// Searches every file selected by `config` and passes the output for each file to `emit`.
+///
+/// Files are searched in parallel, so `emit` is called from several threads, but always with the
+/// whole output of a file at once. The standard input is searched first, and its output is passed
+/// to `emit` as soon as each line is searched.
+pub fn search_paths(
+    config: &Config,
+    emit: impl Fn(&[u8]) + Sync,
+) -> Result<Summary, Box<dyn Error>> {
+    let searcher = build_searcher(config)?;
+    let with_file_name = config.walk.recursive || config.paths.len() > 1;
+    let matched = AtomicBool::new(false);
+    let had_errors = AtomicBool::new(false);
+    // Whether some file already printed lines, so the next one is separated from it when printing
+    // context.
+    let separate_files = Mutex::new(false);
+    // With --sort, the lines of all the files, printed at the end.
+    let ranked = Mutex::new(Vec::new());
+    let (before_context, after_context) = config.context();
+    let prints_context = before_context > 0 || after_context > 0;
+
+    let report = |result: Result<usize, String>| match result {
+        Ok(selected) => {
+            if selected > 0 {
+                matched.store(true, Ordering::Relaxed);
+            }
+            // With -q, the first match is enough to know the exit code.
+            !(config.quiet && selected > 0)
+        }
+        Err(e) => {
+            eprintln!("mygrep: {e}");
+            had_errors.store(true, Ordering::Relaxed);
+            true
+        }
     };
 
-    for line in results {
-        println!("{line}");
+    // Recursive searches of fixed strings skip the files that the index of a searched directory
+    // rules out.
+    let query = Query::new(config).filter(|_| config.walk.recursive);
+    let mut indexes = Vec::new();
+    if query.is_some() {
+        for path in config.paths.iter().filter(|path| Path::new(path).is_dir()) {
+            match Index::load(Path::new(path)) {
+                Ok(index) => indexes.extend(index),
+                Err(e) => _ = report(Err(format!("{path}/{INDEX_FILE}: {e}"))),
+            }
+        }
     }
+    let may_match = |path: &Path| match (&query, indexes.is_empty()) {
+        (Some(query), false) => fs::metadata(path).map_or(true, |metadata| {
+            indexes
+                .iter()
+                .all(|index| index.may_match(path, &metadata, query))
+        }),
+        _ => true,
+    };
 
-    Ok(())
+    if config.paths.iter().any(|path| path == STDIN_PATH) {
+        let mut out = EmitWriter(&emit);
+        let stdin = io::stdin().lock();
+        let name = "(standard input)";
+        let result = if config.sort.is_some() {
+            let mut sink = search::RankingSink::new(config, &searcher, name, with_file_name);
+            let result = searcher.search_reader(stdin, &mut sink);
+            ranked.lock().unwrap().append(&mut sink.lines);
+            result
+        } else if config.search_zip {
+            compressed::search_reader(config, &searcher, name, with_file_name, stdin, &mut out)
+        } else {
+            search::search_reader(config, &searcher, name, with_file_name, stdin, &mut out)
+        };
+        *separate_files.lock().unwrap() = true;
+        if !report(result.map_err(|e| format!("(standard input): {e}"))) {
+            return Ok(summary(&matched, &had_errors));
+        }
+    }
+
+    let paths: Vec<_> = config
+        .paths
+        .iter()
+        .filter(|path| *path != STDIN_PATH)
+        .cloned()
+        .collect();
+    walk::visit(&paths, &config.walk, |path| {
+        let path = match path {
+            Ok(path) => path,
+            Err(e) => return report(Err(e.to_string())),
+        };
+        let mut output = Vec::new();
+        let result = match (&config.replace, config.write) {
+            // What a file without matches would print, like a count of 0.
+            _ if !may_match(path) => {
+                let name = path.display().to_string();
+                search::search_reader(
+                    config,
+                    &searcher,
+                    &name,
+                    with_file_name,
+                    &b""[..],
+                    &mut output,
+                )
+            }
+            (Some(replacement), true) => replace::rewrite_file(
+                config,
+                searcher.matcher(),
+                path,
+                replacement.as_bytes(),
+                &mut output,
+            ),
+            _ if config.sort.is_some() => {
+                let name = path.display().to_string();
+                let mut sink = search::RankingSink::new(config, &searcher, &name, with_file_name);
+                let result = searcher.search_path(path, &mut sink);
+                ranked.lock().unwrap().append(&mut sink.lines);
+                result
+            }
+            _ => search_file(config, &searcher, path, with_file_name, &mut output),
+        }
+        .map_err(|e| format!("{}: {e}", path.display()));
+
+        if !output.is_empty() {
+            let mut separate = separate_files.lock().unwrap();
+            if prints_context && *separate && search::lists_lines(config) {
+                emit(b"--\n");
+            }
+            *separate = true;
+            emit(&output);
+        }
+        report(result)
+    })?;
+
+    if let Some(sort) = config.sort {
+        let mut ranked = ranked.into_inner().unwrap();
+        match sort {
+            SortBy::Path => {
+                ranked.sort_by(|a, b| (&a.path, a.line_number).cmp(&(&b.path, b.line_number)))
+            }
+            SortBy::Score => ranked.sort_by(|a, b| {
+                (a.score, &a.path, a.line_number).cmp(&(b.score, &b.path, b.line_number))
+            }),
+        }
+        for line in ranked {
+            emit(&line.output);
+        }
+    }
+
+    Ok(summary(&matched, &had_errors))
+}
+
+fn summary(matched: &AtomicBool, had_errors: &AtomicBool) -> Summary {
+    Summary {
+        matched: matched.load(Ordering::Relaxed),
+        had_errors: had_errors.load(Ordering::Relaxed),
+    }
 }
 
+/// Searches the file at `path`. With `-z`, compressed files and archives are searched by their
+/// contents.
+fn search_file(
+    config: &Config,
+    searcher: &Searcher,
+    path: &Path,
+    with_file_name: bool,
+    out: &mut Vec<u8>,
+) -> io::Result<usize> {
+    if config.search_zip {
+        if let Some(selected) =
+            compressed::search_file(config, searcher, path, with_file_name, out)?
+        {
+            return Ok(selected);
+        }
+    }
+
+    let name = path.display().to_string();
+    let sink = search::StandardSink::new(config, searcher, &name, with_file_name, out);
+    searcher.search_path(path, sink)
+}
+
+/// Passes everything written to `emit` right away.
+struct EmitWriter<'a, F>(&'a F);
+
+impl<F: Fn(&[u8])> Write for EmitWriter<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Builds the searcher of the command line.
+fn build_searcher(config: &Config) -> Result<Searcher, Box<dyn Error>> {
+    let (before_context, after_context) = config.context();
+    // Lines of binary files are not printed, but they are counted.
+    let binary = match search::lists_lines(config) && !config.json {
+        true => BinaryDetection::Quit,
+        false => BinaryDetection::None,
+    };
+    Ok(SearcherBuilder::new()
+        .before_context(before_context)
+        .after_context(after_context)
+        .max_count(config.max_count)
+        .invert_match(config.invert_match)
+        .binary_detection(binary)
+        .encoding(config.encoding)
+        .bom_sniffing(true)
+        .build(build_matcher(config)?))
+}
+
+fn build_matcher(config: &Config) -> Result<Box<dyn Matcher>, Box<dyn Error>> {
+    let patterns = &config.patterns;
+    if let Some(max_distance) = config.fuzzy {
+        let [pattern] = patterns.as_slice() else {
+            return Err("--fuzzy searches for a single pattern".into());
+        };
+        return Ok(Box::new(FuzzyMatcher::new(
+            pattern,
+            max_distance,
+            config.ignore_case,
+        )?));
+    }
+    if !config.regex && !config.word_regexp && !config.line_regexp {
+        if let [pattern] = patterns.as_slice() {
+            return Ok(Box::new(LiteralMatcher::new(pattern, config.ignore_case)));
+        }
+        // Aho-Corasick only folds the case of ASCII letters.
+        if !config.ignore_case || patterns.iter().all(|pattern| pattern.is_ascii()) {
+            return Ok(Box::new(LiteralSetMatcher::new(
+                patterns,
+                config.ignore_case,
+            )));
+        }
+    }
+
+    let patterns = patterns.iter().map(|pattern| {
+        let pattern = match config.regex {
+            true => pattern.clone(),
+            false => regex::escape(pattern),
+        };
+        if config.line_regexp {
+            format!("^(?:{pattern})$")
+        } else if config.word_regexp {
+            format!(r"\b(?:{pattern})\b")
+        } else {
+            pattern
+        }
+    });
+    match RegexMatcher::new_many(&patterns.collect::<Vec<_>>(), config.ignore_case) {
+        Ok(matcher) => Ok(Box::new(matcher)),
+        // Syntax errors point at the invalid part of the pattern.
+        Err(e) => match e.syntax_error() {
+            Some(syntax_error) => Err(syntax_error.to_string().into()),
+            None => Err(e),
+        },
+    }
+}
+
+/// Searches for a pattern in files.
+#[derive(Parser)]
+#[command(
+    name = "mygrep",
+    version,
+    args_conflicts_with_subcommands = true,
+    disable_help_subcommand = true
+)]
 pub struct Config {
-    pub query: String,
-    pub file_path: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// The pattern to search for, unless patterns are given with -e or -f.
+    pub query: Option<String>,
+    /// Files or directories to search. `-` is the standard input.
+    pub paths: Vec<String>,
+    /// Match case insensitively. Also enabled by setting the IGNORE_CASE environment variable.
+    #[arg(short, long, overrides_with = "smart_case")]
     pub ignore_case: bool,
+    /// Match case insensitively, unless the pattern contains an uppercase letter.
+    #[arg(short = 'S', long, overrides_with = "ignore_case")]
+    pub smart_case: bool,
+    /// Search for PATTERN. Can be repeated to search for any of several patterns.
+    #[arg(short = 'e', long = "regexp", value_name = "PATTERN")]
+    pub regexps: Vec<String>,
+    /// Search for the patterns in FILE, one per line. Can be repeated.
+    #[arg(short = 'f', long = "file", value_name = "FILE")]
+    pub pattern_files: Vec<String>,
+    /// All the patterns to search for, gathered from the query, -e and -f.
+    #[arg(skip)]
+    pub patterns: Vec<String>,
+    /// Treat the pattern as a regular expression.
+    #[arg(
+        short = 'E',
+        long = "extended-regexp",
+        overrides_with = "fixed_strings"
+    )]
+    pub regex: bool,
+    /// Treat the pattern as a fixed string (the default).
+    #[arg(short = 'F', long, overrides_with = "regex")]
+    pub fixed_strings: bool,
+    /// Select non-matching lines.
+    #[arg(short = 'v', long)]
+    pub invert_match: bool,
+    /// Only match whole words.
+    #[arg(short, long)]
+    pub word_regexp: bool,
+    /// Only match whole lines.
+    #[arg(short = 'x', long)]
+    pub line_regexp: bool,
+    /// Print the number of selected lines per file.
+    #[arg(short, long)]
+    pub count: bool,
+    /// Only print the names of files with selected lines.
+    #[arg(short = 'l', long, conflicts_with = "files_without_match")]
+    pub files_with_matches: bool,
+    /// Only print the names of files without selected lines.
+    #[arg(short = 'L', long)]
+    pub files_without_match: bool,
+    /// Prefix lines with their line number.
+    #[arg(short = 'n', long)]
+    pub line_number: bool,
+    /// Stop searching a file after NUM selected lines.
+    #[arg(short = 'm', long, value_name = "NUM")]
+    pub max_count: Option<usize>,
+    /// Print NUM lines of context after each selected line.
+    #[arg(short = 'A', long, value_name = "NUM")]
+    pub after_context: Option<usize>,
+    /// Print NUM lines of context before each selected line.
+    #[arg(short = 'B', long, value_name = "NUM")]
+    pub before_context: Option<usize>,
+    /// Print NUM lines of context before and after each selected line.
+    #[arg(short = 'C', long, value_name = "NUM")]
+    pub context: Option<usize>,
+    /// When to highlight matches, file names and line numbers.
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    pub color: ColorChoice,
+    /// Print one JSON object per selected or context line, with the matches in it.
+    #[arg(long, conflicts_with_all = ["count", "files_with_matches", "files_without_match"])]
+    pub json: bool,
+    /// Select lines that contain the pattern with at most NUM edits, which are insertions, deletions
+    /// or substitutions of a character. The best matching window of each line is highlighted.
+    #[arg(
+        long,
+        value_name = "NUM",
+        conflicts_with_all = ["regex", "word_regexp", "line_regexp"],
+    )]
+    pub fuzzy: Option<usize>,
+    /// Print the selected lines once every file is searched, sorted in ORDER.
+    #[arg(
+        long,
+        value_name = "ORDER",
+        conflicts_with_all = [
+            "after_context",
+            "before_context",
+            "context",
+            "count",
+            "files_with_matches",
+            "files_without_match",
+            "search_zip",
+            "write",
+        ],
+    )]
+    pub sort: Option<SortBy>,
+    /// Print selected lines with every match replaced by REPLACEMENT, where `$1` or `${name}` is
+    /// the text of a capture group and `$0` the whole match.
+    #[arg(long, value_name = "REPLACEMENT")]
+    pub replace: Option<String>,
+    /// Rewrite the files in place with the replacements instead of printing them, and print how
+    /// many were made in each file.
+    #[arg(
+        long,
+        requires = "replace",
+        conflicts_with_all = ["invert_match", "count", "files_with_matches", "files_without_match", "json", "quiet"],
+    )]
+    pub write: bool,
+    /// Search the decompressed contents of .gz, .zst and .bz2 files, and the files inside .tar and
+    /// .zip archives, which are named like `archive.tar:inner/path`.
+    #[arg(short = 'z', long, conflicts_with = "write")]
+    pub search_zip: bool,
+    /// Decode the searched text from ENCODING, like utf-16le, latin1 or shift_jis, and print it as
+    /// UTF-8. Text starting with a UTF-8 or UTF-16 byte order mark is always decoded with the
+    /// encoding of the mark.
+    #[arg(long, value_name = "ENCODING", value_parser = parse_encoding, conflicts_with = "write")]
+    pub encoding: Option<&'static Encoding>,
+    /// Print nothing and exit with status 0 on the first match.
+    #[arg(short, long, alias = "silent")]
+    pub quiet: bool,
+    #[command(flatten)]
+    pub walk: WalkOptions,
 }
 
 impl Config {
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
-        args.next(); // Skip the program name
-
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Missing query argument"),
+    /// Parses the command line arguments.
+    pub fn build(args: impl Iterator<Item = String>) -> Result<Config, clap::Error> {
+        let args: Vec<String> = args.collect();
+        // `index` is only the subcommand when one of its own follows, so that `mygrep index FILE`
+        // searches FILE for the word.
+        let searches_index = args.get(1).is_some_and(|arg| arg == "index")
+            && !args
+                .get(2)
+                .is_some_and(|arg| matches!(arg.as_str(), "build" | "update" | "-h" | "--help"));
+        let mut config = match searches_index {
+            true => {
+                let mut command = search_command();
+                let matches = command.try_get_matches_from_mut(args)?;
+                Config::from_arg_matches(&matches).map_err(|e| e.format(&mut command))?
+            }
+            false => Config::try_parse_from(args)?,
         };
+        if config.command.is_some() {
+            return Ok(config);
+        }
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Missing file path argument"),
-        };
+        // With -e or -f, the query is the first path instead.
+        if config.regexps.is_empty() && config.pattern_files.is_empty() {
+            match config.query.take() {
+                Some(query) => config.patterns.push(query),
+                None => {
+                    return Err(Config::command()
+                        .error(ErrorKind::MissingRequiredArgument, "Missing query argument"))
+                }
+            }
+        } else if let Some(path) = config.query.take() {
+            config.paths.insert(0, path);
+        }
+        config.patterns.extend(config.regexps.iter().cloned());
+        for path in &config.pattern_files {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| Config::command().error(ErrorKind::Io, format!("{path}: {e}")))?;
+            config
+                .patterns
+                .extend(contents.lines().map(|line| line.to_string()));
+        }
 
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // Without paths, a recursive search starts from the current directory, and any other
+        // search reads the standard input.
+        if config.paths.is_empty() {
+            let path = if config.walk.recursive {
+                "."
+            } else {
+                STDIN_PATH
+            };
+            config.paths.push(String::from(path));
+        }
+        if config.write && config.paths.iter().any(|path| path == STDIN_PATH) {
+            return Err(Config::command().error(
+                ErrorKind::ArgumentConflict,
+                "--write cannot rewrite the standard input",
+            ));
+        }
 
-        Ok(Config {
-            query,
-            file_path,
-            ignore_case,
-        })
+        if config.smart_case {
+            config.ignore_case = !config
+                .patterns
+                .iter()
+                .any(|pattern| matcher::has_uppercase(pattern, config.regex));
+        } else {
+            config.ignore_case |= env::var("IGNORE_CASE").is_ok();
+        }
+
+        Ok(config)
+    }
+
+    /// The number of lines of context to print before and after selected lines. -A and -B take
+    /// precedence over -C.
+    pub fn context(&self) -> (usize, usize) {
+        (
+            self.before_context.or(self.context).unwrap_or(0),
+            self.after_context.or(self.context).unwrap_or(0),
+        )
     }
 }
 
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    contents
-        .lines()
-        .filter(|line| line.contains(query))
-        .collect()
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// The command line parser without the subcommands.
+fn search_command() -> clap::Command {
+    let command = Config::command();
+    let search = clap::Command::new("mygrep")
+        .version(env!("CARGO_PKG_VERSION"))
+        .args(command.get_arguments().cloned())
+        .groups(command.get_groups().cloned());
+    match command.get_about() {
+        Some(about) => search.about(about.clone()),
+        None => search,
+    }
+}
+
+/// Subcommands, which take the place of the pattern. The word "index" is searched for unless
+/// `build` or `update` follows it.
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Build or update the trigram index of a directory, which recursive searches of fixed strings
+    /// in it use to skip the files that cannot match.
+    #[command(subcommand)]
+    Index(IndexCommand),
+}
+
+#[derive(clap::Subcommand)]
+pub enum IndexCommand {
+    /// Index every file under DIR from scratch.
+    Build(IndexArgs),
+    /// Only reindex the files under DIR that are new or changed since they were indexed.
+    Update(IndexArgs),
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    contents
-        .lines()
-        .filter(|line| line.to_lowercase().contains(&query.to_lowercase()))
+#[derive(clap::Args)]
+pub struct IndexArgs {
+    /// The directory to index.
+    #[arg(default_value = ".")]
+    pub dir: PathBuf,
+    /// Number of threads walking and indexing files.
+    #[arg(short = 'j', long, value_name = "NUM", default_value_t = default_threads())]
+    pub threads: usize,
+}
+
+/// Builds or updates the index of a directory, and prints what changed.
+pub fn run_index(command: &IndexCommand) -> Result<(), Box<dyn Error>> {
+    let (args, previous) = match command {
+        IndexCommand::Build(args) => (args, None),
+        IndexCommand::Update(args) => (args, Index::load(&args.dir)?),
+    };
+    let mut index = previous.unwrap_or_else(|| Index::new(&args.dir));
+    let stats = index.update(args.threads)?;
+    index.save()?;
+
+    println!(
+        "{}: {} files, {} read, {} removed",
+        args.dir.join(INDEX_FILE).display(),
+        stats.files,
+        stats.reindexed,
+        stats.removed
+    );
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortBy {
+    /// By file path, then line number.
+    Path,
+    /// By fuzzy distance, best first, then by path. Lines all score 0 without --fuzzy.
+    Score,
+}
+
+/// Parses an encoding label of the WHATWG Encoding Standard, which `encoding_rs` implements.
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding `{label}`"))
+}
+
+/// The default number of search threads.
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// A line selected by a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMatch {
+    /// The line number, starting at 1.
+    pub line_number: usize,
+    /// The byte range of the line in the searched text, without its line terminator.
+    pub line: Range<usize>,
+    /// The byte ranges of the matches in the searched text.
+    pub matches: Vec<Range<usize>>,
+}
+
+impl LineMatch {
+    /// The text of the line in `contents`, the text that was searched.
+    pub fn text<'a>(&self, contents: &'a str) -> &'a str {
+        &contents[self.line.clone()]
+    }
+}
+
+pub fn search(query: &str, contents: &str) -> Vec<LineMatch> {
+    search_with(&LiteralMatcher::new(query, false), contents)
+}
+
+pub fn search_case_insensitive(query: &str, contents: &str) -> Vec<LineMatch> {
+    search_with(&LiteralMatcher::new(query, true), contents)
+}
+
+pub fn search_with(matcher: &dyn Matcher, contents: &str) -> Vec<LineMatch> {
+    search_bytes(matcher, contents.as_bytes())
+}
+
+/// Searches `contents`, which does not need to be valid UTF-8.
+pub fn search_bytes(matcher: &dyn Matcher, contents: &[u8]) -> Vec<LineMatch> {
+    line_ranges(contents)
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let start = line.start;
+            let matches: Vec<_> = matcher
+                .find_iter(&contents[line.clone()])
+                .map(|m| start + m.range.start..start + m.range.end)
+                .collect();
+            (!matches.is_empty()).then_some(LineMatch {
+                line_number: index + 1,
+                line,
+                matches,
+            })
+        })
         .collect()
 }
 
+/// The byte ranges of the lines in `contents`, split like `str::lines`.
+fn line_ranges(contents: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut start = 0;
+    contents.split_inclusive(|&b| b == b'\n').map(move |line| {
+        let range = start..start + line.len();
+        start = range.end;
+        range.start..range.start + searcher::trim_line_terminator(line).len()
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -75,7 +689,13 @@ safe, fast, productive.
 Pick three.
 Duct tape.";
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+        let results = search(query, contents);
+        assert_eq!(
+            vec!["safe, fast, productive."],
+            results.iter().map(|m| m.text(contents)).collect::<Vec<_>>()
+        );
+        assert_eq!(2, results[0].line_number);
+        assert_eq!(vec![21..25], results[0].matches);
     }
 
     #[test]
@@ -90,6 +710,509 @@ Trust me.";
         assert_eq!(
             vec!["Rust:", "Trust me."],
             search_case_insensitive(query, contents)
+                .iter()
+                .map(|m| m.text(contents))
+                .collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn regex_flags() {
+        let args = ["mygrep", "-E", "^T", "poem.txt"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(config.regex);
+        assert_eq!(vec!["^T"], config.patterns);
+
+        let args = ["mygrep", "-E", "-F", "^T", "poem.txt"].map(String::from);
+        assert!(!Config::build(args.into_iter()).unwrap().regex);
+
+        let args = ["mygrep", "-X", "^T", "poem.txt"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn paths_and_globs() {
+        let args = ["mygrep", "-r", "--include", "*.rs", "--exclude=*.txt", "to"];
+        let config = Config::build(args.map(String::from).into_iter()).unwrap();
+        assert_eq!(vec!["."], config.paths);
+        assert_eq!(vec!["*.rs"], config.walk.include);
+        assert_eq!(vec!["*.txt"], config.walk.exclude);
+
+        let args = ["mygrep", "to", "a.txt", "b.txt"];
+        let config = Config::build(args.map(String::from).into_iter()).unwrap();
+        assert!(!config.walk.recursive);
+        assert_eq!(vec!["a.txt", "b.txt"], config.paths);
+
+        let args = ["mygrep", "to"].map(String::from);
+        assert_eq!(vec!["-"], Config::build(args.into_iter()).unwrap().paths);
+    }
+
+    #[test]
+    fn subcommand_names_are_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "the index\nno help\nnothing\n").unwrap();
+        let path = path.display().to_string();
+
+        for (query, expected) in [("index", "the index\n"), ("help", "no help\n")] {
+            let config =
+                Config::build(["mygrep", query, &path].map(String::from).into_iter()).unwrap();
+            assert!(config.command.is_none());
+            let output = Mutex::new(Vec::new());
+            search_paths(&config, |bytes| {
+                output.lock().unwrap().extend_from_slice(bytes)
+            })
+            .unwrap();
+            assert_eq!(expected.as_bytes(), output.into_inner().unwrap());
+        }
+
+        let args = ["mygrep", "index", "update", "dir"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(matches!(
+            config.command,
+            Some(Command::Index(IndexCommand::Update(_)))
+        ));
+    }
+
+    #[test]
+    fn several_patterns() {
+        let args = ["mygrep", "-e", "frog", "-e", "bog", "poem.txt"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(vec!["frog", "bog"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.paths);
+
+        let dir = tempfile::tempdir().unwrap();
+        let patterns = dir.path().join("patterns");
+        std::fs::write(&patterns, "nobody\r\n^How\n").unwrap();
+        let patterns = patterns.display().to_string();
+        let args = ["mygrep", "-E", "-e", "frog", "-f", &patterns].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(vec!["frog", "nobody", "^How"], config.patterns);
+
+        let args = ["mygrep", "-f", "missing-patterns"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+        assert!(Config::build(["mygrep"].map(String::from).into_iter()).is_err());
+
+        assert_eq!(3, search_poem(&["-e", "frog", "-e", "nobody"]).1);
+        assert_eq!(3, search_poem(&["-i", "-e", "FROG", "-e", "NOBODY"]).1);
+        assert_eq!(2, search_poem(&["-w", "-e", "frog", "-e", "bog"]).1);
+        assert_eq!(4, search_poem(&["-E", "-f", &patterns]).1);
+    }
+
+    #[test]
+    fn json_reports_the_matching_pattern() {
+        let (output, _) = search_poem(&["--json", "-e", "Who", "-e", "you"]);
+        let line: serde_json::Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        let patterns: Vec<_> = line["submatches"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["pattern"].as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["Who", "you"], patterns);
+    }
+
+    #[test]
+    fn threads() {
+        let args = ["mygrep", "-j", "3", "to", "poem.txt"].map(String::from);
+        assert_eq!(3, Config::build(args.into_iter()).unwrap().walk.threads);
+
+        let args = ["mygrep", "-j1", "to", "poem.txt"].map(String::from);
+        assert_eq!(1, Config::build(args.into_iter()).unwrap().walk.threads);
+
+        let args = ["mygrep", "-jx", "to", "poem.txt"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    fn search_poem(args: &[&str]) -> (String, usize) {
+        let args = ["mygrep"].iter().chain(args).chain(&["poem.txt"]);
+        let config = Config::build(args.map(|arg| arg.to_string())).unwrap();
+        let searcher = build_searcher(&config).unwrap();
+        let mut output = Vec::new();
+        let selected = search_file(
+            &config,
+            &searcher,
+            Path::new("poem.txt"),
+            false,
+            &mut output,
+        )
+        .unwrap();
+        (String::from_utf8(output).unwrap(), selected)
+    }
+
+    /// Runs mygrep with `flags` on the directory `root`, and returns what it printed, with `root`
+    /// left out of the paths.
+    fn search_output(root: &Path, flags: &[&str]) -> String {
+        let root = root.display().to_string();
+        let args = ["mygrep"].iter().chain(flags).map(|arg| arg.to_string());
+        let config = Config::build(args.chain([root.clone()])).unwrap();
+        let output = Mutex::new(Vec::new());
+        search_paths(&config, |bytes| {
+            output.lock().unwrap().extend_from_slice(bytes)
+        })
+        .unwrap();
+        String::from_utf8(output.into_inner().unwrap())
+            .unwrap()
+            .replace(&root, "")
+    }
+
+    #[test]
+    fn output_options() {
+        assert_eq!(
+            (String::from("2:Are you nobody, too?\n"), 1),
+            search_poem(&["-n", "too"])
+        );
+        assert_eq!((String::from("4\n"), 4), search_poem(&["-c", "-i", "to"]));
+        assert_eq!(
+            (String::from("I'm nobody! Who are you?\n"), 1),
+            search_poem(&["-m", "1", "nobody"])
+        );
+        assert_eq!(8, search_poem(&["-v", "nobody"]).1);
+        assert_eq!(
+            (String::from("poem.txt\n"), 1),
+            search_poem(&["-l", "frog"])
+        );
+        assert_eq!((String::new(), 1), search_poem(&["-L", "frog"]));
+        assert_eq!((String::new(), 1), search_poem(&["-q", "frog"]));
+    }
+
+    #[test]
+    fn whole_words_and_lines() {
+        assert_eq!(3, search_poem(&["body"]).1);
+        assert_eq!(0, search_poem(&["-w", "body"]).1);
+        assert_eq!(2, search_poem(&["-w", "nobody"]).1);
+        assert_eq!(0, search_poem(&["-x", "nobody"]).1);
+        assert_eq!(1, search_poem(&["-x", "-E", "To an \\w+ bog!"]).1);
+    }
+
+    #[test]
+    fn context_windows_are_merged() {
+        let (output, selected) = search_poem(&["-n", "-C", "1", "nobody"]);
+        assert_eq!(2, selected);
+        assert_eq!(
+            "\
+1:I'm nobody! Who are you?
+2:Are you nobody, too?
+3-Then there's a pair of us - don't tell!
+",
+            output
+        );
+
+        let (output, _) = search_poem(&["-n", "-E", "-A", "1", "-B", "0", "-C", "5", "pair|frog"]);
+        assert_eq!(
+            "\
+3:Then there's a pair of us - don't tell!
+4-They'd banish us, you know.
+--
+7:How public, like a frog
+8-To tell your name the livelong day
+",
+            output
+        );
+    }
+
+    #[test]
+    fn context_after_max_count() {
+        let (output, selected) = search_poem(&["-m", "1", "-A", "2", "-E", "^How"]);
+        assert_eq!(1, selected);
+        assert_eq!(
+            "\
+How dreary to be somebody!
+How public, like a frog
+To tell your name the livelong day
+",
+            output
+        );
+    }
+
+    #[test]
+    fn binary_files() {
+        let config = Config::build(["mygrep", "-n", "b"].map(String::from).into_iter()).unwrap();
+        let searcher = build_searcher(&config).unwrap();
+        let search = |contents: &[u8]| {
+            let mut output = Vec::new();
+            let selected =
+                search::search_reader(&config, &searcher, "file", false, contents, &mut output)
+                    .unwrap();
+            (output, selected)
+        };
+
+        // Invalid UTF-8 is searched and printed as is.
+        assert_eq!((b"2:b\xff\n".to_vec(), 1), search(b"a\nb\xff\r\nc"));
+        assert_eq!(
+            (b"Binary file file matches\n".to_vec(), 1),
+            search(b"a\0\nb\nb\n")
+        );
+        assert_eq!((Vec::new(), 0), search(b"a\0\nc\n"));
+    }
+
+    #[test]
+    fn large_files_are_mapped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.txt");
+        let mut contents = "hay\n".repeat(searcher::MMAP_THRESHOLD as usize / 4);
+        contents.push_str("needle\n");
+        std::fs::write(&path, contents).unwrap();
+
+        let config =
+            Config::build(["mygrep", "-c", "needle"].map(String::from).into_iter()).unwrap();
+        let searcher = build_searcher(&config).unwrap();
+        let mut output = Vec::new();
+        let selected = search_file(&config, &searcher, &path, false, &mut output).unwrap();
+        assert_eq!(1, selected);
+        assert_eq!(b"1\n", output.as_slice());
+    }
+
+    #[test]
+    fn colored_output() {
+        let (output, _) = search_poem(&["--color=always", "-n", "-i", "FROG"]);
+        assert_eq!(
+            "\x1b[32m7\x1b[0m\x1b[36m:\x1b[0mHow public, like a \x1b[1;31mfrog\x1b[0m\n",
+            output
+        );
+
+        let (output, _) = search_poem(&["--color=never", "frog"]);
+        assert_eq!("How public, like a frog\n", output);
+    }
+
+    #[test]
+    fn json_output() {
+        let (output, _) = search_poem(&["--json", "-E", "-A", "1", "a (frog|bog)"]);
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(2, lines.len());
+        assert_eq!(
+            serde_json::json!({
+                "type": "match",
+                "path": "poem.txt",
+                "line_number": 7,
+                "absolute_offset": 142,
+                "line": "How public, like a frog",
+                "submatches": [
+                    {"match": "a frog", "start": 17, "end": 23, "pattern": "a (frog|bog)"},
+                ],
+            }),
+            lines[0]
+        );
+        assert_eq!("context", lines[1]["type"]);
+        assert_eq!(8, lines[1]["line_number"]);
+    }
+
+    #[test]
+    fn replace_preview() {
+        assert_eq!(
+            "How public, like a toad\n",
+            search_poem(&["--replace", "toad", "frog"]).0
+        );
+        assert_eq!(
+            "7:How public, frog a like\n",
+            search_poem(&["-n", "-E", "--replace", "$3 $2 $1", r"(like) (a) (\w+)"]).0
+        );
+        assert_eq!(
+            "T\x1b[1;31mhey\x1b[0m banish us, you know.\n",
+            search_poem(&[
+                "--color=always",
+                "-E",
+                "--replace",
+                "${x}",
+                "(?<x>[a-z]+)'d"
+            ])
+            .0
+        );
+    }
+
+    #[test]
+    fn write_replacements() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "a frog\r\nno match\nfrog and frog").unwrap();
+        let untouched = dir.path().join("other.txt");
+        std::fs::write(&untouched, "nothing\n").unwrap();
+
+        let output = search_output(dir.path(), &["-r", "--replace", "toad", "--write", "frog"]);
+
+        assert_eq!("/notes.txt: 3 replacements in 2 lines\n", output);
+        assert_eq!(
+            "a toad\r\nno match\ntoad and toad",
+            std::fs::read_to_string(&path).unwrap()
+        );
+        assert_eq!("nothing\n", std::fs::read_to_string(&untouched).unwrap());
+
+        let args = ["mygrep", "--write", "frog", "poem.txt"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+        let args = ["mygrep", "--replace", "toad", "--write", "frog"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_through_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "a frog\n").unwrap();
+        let link = dir.path().join("link.txt");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+
+        let args = ["mygrep", "--replace", "toad", "--write", "frog"];
+        let args = args.iter().map(|arg| arg.to_string());
+        let config = Config::build(args.chain([link.display().to_string()])).unwrap();
+        search_paths(&config, |_| {}).unwrap();
+
+        // The file behind the link is rewritten, and the link is kept.
+        assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!("a toad\n", std::fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn compressed_files_and_archives() {
+        use std::io::Write as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = b"started\nneedle found\nstopped\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(log).unwrap();
+        std::fs::write(dir.path().join("a.log.gz"), gzip.finish().unwrap()).unwrap();
+        // Compressed files are also recognized by their magic bytes.
+        let zstd = zstd::encode_all(&log[..], 0).unwrap();
+        std::fs::write(dir.path().join("b.log.1"), zstd).unwrap();
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(log).unwrap();
+        std::fs::write(dir.path().join("c.log.bz2"), bzip2.finish().unwrap()).unwrap();
+
+        let gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(gzip);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(log.len() as u64);
+        header.set_cksum();
+        tar.append_data(&mut header, "logs/d.log", &log[..])
+            .unwrap();
+        let tar = tar.into_inner().unwrap().finish().unwrap();
+        std::fs::write(dir.path().join("d.tar.gz"), tar).unwrap();
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("logs/e.log", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(log).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        std::fs::write(dir.path().join("e.zip"), zip).unwrap();
+
+        let output = search_output(dir.path(), &["-z", "-r", "-j1", "-n", "needle"]);
+        let expected = [
+            "a.log.gz",
+            "b.log.1",
+            "c.log.bz2",
+            "d.tar.gz:logs/d.log",
+            "e.zip:logs/e.log",
+        ]
+        .map(|name| format!("/{name}:2:needle found\n"))
+        .concat();
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn fuzzy_matching() {
+        assert_eq!(
+            "How public, like a \x1b[1;31mfrog\x1b[0m\n",
+            search_poem(&["--fuzzy", "1", "--color=always", "frug"]).0
+        );
+        assert_eq!(1, search_poem(&["--fuzzy", "1", "-i", "FRUG"]).1);
+        assert_eq!(0, search_poem(&["--fuzzy", "1", "fruq"]).1);
+
+        let args = ["mygrep", "--fuzzy", "1", "-e", "a", "-e", "b", "poem.txt"];
+        let config = Config::build(args.map(String::from).into_iter()).unwrap();
+        assert!(build_searcher(&config).is_err());
+        let args = ["mygrep", "--fuzzy", "1", "-E", "frug", "poem.txt"];
+        assert!(Config::build(args.map(String::from).into_iter()).is_err());
+    }
+
+    #[test]
+    fn sort_by_score() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a frog\nno match\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "a bog\n").unwrap();
+        let sorted =
+            |order| search_output(dir.path(), &["-r", "--fuzzy", "1", "--sort", order, "bog"]);
+
+        assert_eq!("/b.txt:a bog\n/a.txt:a frog\n", sorted("score"));
+        assert_eq!("/a.txt:a frog\n/b.txt:a bog\n", sorted("path"));
+    }
+
+    #[test]
+    fn index_skips_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let hay = dir.path().join("hay.txt");
+        std::fs::write(dir.path().join("needle.txt"), "a needle\n").unwrap();
+        std::fs::write(&hay, "a haystack\n").unwrap();
+        let command = IndexCommand::Build(IndexArgs {
+            dir: dir.path().to_path_buf(),
+            threads: 1,
+        });
+        run_index(&command).unwrap();
+
+        // Rewrite the file without changing its size or modification time, so that only the
+        // index can tell that it has no needle.
+        let modified = std::fs::metadata(&hay).unwrap().modified().unwrap();
+        std::fs::write(&hay, "a needle!!\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&hay).unwrap();
+        file.set_modified(modified).unwrap();
+
+        assert_eq!(
+            "/hay.txt:0\n/needle.txt:1\n",
+            search_output(dir.path(), &["-r", "-j1", "-c", "needle"])
+        );
+        // Regular expressions do not use the index.
+        assert_eq!(
+            "/hay.txt:1\n/needle.txt:1\n",
+            search_output(dir.path(), &["-r", "-j1", "-c", "-E", "needle"])
+        );
+    }
+
+    #[test]
+    fn decode_encodings() {
+        let dir = tempfile::tempdir().unwrap();
+        let utf16: Vec<u8> = "\u{feff}tea\r\ncafé au lait\r\n"
+            .encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+        std::fs::write(dir.path().join("utf16.log"), utf16).unwrap();
+        std::fs::write(dir.path().join("latin1.txt"), b"un caf\xe9 noir\n").unwrap();
+
+        let search = |flags: &[&str]| {
+            let args = ["-r", "-j1", "--sort=path"].iter().chain(flags);
+            search_output(dir.path(), &args.copied().collect::<Vec<_>>())
+        };
+        // The byte order mark is sniffed, and lines are printed in UTF-8.
+        assert_eq!("/utf16.log:café au lait\n", search(&["café"]));
+        assert_eq!(
+            "/latin1.txt:un café noir\n/utf16.log:café au lait\n",
+            search(&["--encoding", "latin1", "café"])
+        );
+
+        let args = ["mygrep", "--encoding", "klingon", "café"];
+        let Err(e) = Config::build(args.iter().map(|arg| arg.to_string())) else {
+            panic!("unknown encodings are rejected");
+        };
+        assert_eq!(ErrorKind::ValueValidation, e.kind());
+    }
+
+    #[test]
+    fn smart_case() {
+        let config = |args: &[&str]| {
+            let args = ["mygrep"].iter().chain(args).chain(&["poem.txt"]);
+            Config::build(args.map(|arg| arg.to_string())).unwrap()
+        };
+
+        assert!(config(&["-S", "frog"]).ignore_case);
+        assert!(!config(&["-S", "Frog"]).ignore_case);
+        assert!(config(&["-S", "-E", r"\Wfrog"]).ignore_case);
+        // The last of -i and -S wins.
+        assert!(config(&["-S", "-i", "Frog"]).ignore_case);
+        assert!(!config(&["-i", "-S", "Frog"]).ignore_case);
+
+        assert_eq!(4, search_poem(&["-S", "to"]).1);
+        assert_eq!(2, search_poem(&["-S", "To"]).1);
+    }
 }
@@ -1,15 +1,29 @@
-use mygrep::Config;
+use mygrep::{Command, Config};
 use std::env;
-use std::process;
+use std::process::ExitCode;
 
-fn main() {
-    let config = Config::build(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
-    });
+fn main() -> ExitCode {
+    let config = Config::build(env::args()).unwrap_or_else(|err| err.exit());
+    if let Some(Command::Index(command)) = &config.command {
+        return match mygrep::run_index(command) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Application error: {e}");
+                ExitCode::from(2)
+            }
+        };
+    }
+    let quiet = config.quiet;
 
-    if let Err(e) = mygrep::run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    // Like grep: 0 when a line was selected, 1 when none was, and 2 on errors unless -q already
+    // found a match.
+    match mygrep::run(config) {
+        Ok(summary) if summary.matched && (quiet || !summary.had_errors) => ExitCode::SUCCESS,
+        Ok(summary) if !summary.had_errors => ExitCode::from(1),
+        Ok(_) => ExitCode::from(2),
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            ExitCode::from(2)
+        }
     }
 }
@@ -0,0 +1,383 @@
+use std::ops::Range;
+
+use aho_corasick::AhoCorasick;
+use memchr::memmem;
+use regex::bytes::{Regex, RegexBuilder};
+use regex_automata::meta;
+use regex_automata::util::{interpolate, syntax};
+use regex_automata::Input;
+use regex_syntax::ast::{self, Ast};
+
+/// A match of one of the patterns of a matcher.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The index of the pattern that matched.
+    pub pattern: usize,
+    pub range: Range<usize>,
+}
+
+/// Finds occurrences of one or more patterns in a line. Lines are bytes, which do not need to be
+/// valid UTF-8. Matchers are shared by the searching threads.
+pub trait Matcher: Send + Sync {
+    /// Returns the first match in `haystack` at or after `start`.
+    fn find_match_at(&self, haystack: &[u8], start: usize) -> Option<Match>;
+
+    /// Returns the byte range of the first match in `haystack` at or after `start`.
+    fn find_at(&self, haystack: &[u8], start: usize) -> Option<Range<usize>> {
+        self.find_match_at(haystack, start).map(|m| m.range)
+    }
+
+    fn find(&self, haystack: &[u8]) -> Option<Range<usize>> {
+        self.find_at(haystack, 0)
+    }
+
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find(haystack).is_some()
+    }
+
+    /// How well `haystack` matches, for ranking lines, where lower is better. Exact matchers
+    /// score every matching line 0.
+    fn score(&self, haystack: &[u8]) -> Option<usize> {
+        self.is_match(haystack).then_some(0)
+    }
+
+    /// Appends `replacement` for the match `m` in `haystack` to `dst`, where `$N` and `${name}`
+    /// stand for the text of capture groups. Fixed strings only have group `$0`, the whole match.
+    fn interpolate(&self, haystack: &[u8], m: &Match, replacement: &[u8], dst: &mut Vec<u8>) {
+        interpolate::bytes(
+            replacement,
+            |group, dst| {
+                if group == 0 {
+                    dst.extend_from_slice(&haystack[m.range.clone()]);
+                }
+            },
+            |_| None,
+            dst,
+        );
+    }
+}
+
+impl dyn Matcher + '_ {
+    /// Iterates over the successive non-overlapping matches in `haystack`.
+    pub fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> FindIter<'h> {
+        FindIter {
+            matcher: self,
+            haystack,
+            start: 0,
+        }
+    }
+
+    /// Appends `haystack` to `dst` with every match replaced by `replacement`, and returns the
+    /// number of replaced matches.
+    pub fn replace_all(&self, haystack: &[u8], replacement: &[u8], dst: &mut Vec<u8>) -> usize {
+        let mut written = 0;
+        let mut replaced = 0;
+        for found in self.find_iter(haystack) {
+            dst.extend_from_slice(&haystack[written..found.range.start]);
+            self.interpolate(haystack, &found, replacement, dst);
+            written = found.range.end;
+            replaced += 1;
+        }
+        dst.extend_from_slice(&haystack[written..]);
+        replaced
+    }
+}
+
+pub struct FindIter<'h> {
+    matcher: &'h dyn Matcher,
+    haystack: &'h [u8],
+    start: usize,
+}
+
+impl Iterator for FindIter<'_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        if self.start > self.haystack.len() {
+            return None;
+        }
+        let found = self.matcher.find_match_at(self.haystack, self.start)?;
+        // Step over empty matches so the search always moves forward.
+        self.start = match found.range.is_empty() {
+            true => found.range.end + 1,
+            false => found.range.end,
+        };
+        Some(found)
+    }
+}
+
+/// Matches a fixed string.
+pub struct LiteralMatcher {
+    finder: memmem::Finder<'static>,
+    /// Case-insensitive literals are matched by an escaped regex. It compares lines with the Unicode
+    /// simple case folding of the needle as it scans them, so lines are never lowercased.
+    folded: Option<Regex>,
+}
+
+impl LiteralMatcher {
+    pub fn new(needle: &str, ignore_case: bool) -> LiteralMatcher {
+        let folded = ignore_case.then(|| {
+            RegexBuilder::new(&regex::escape(needle))
+                .case_insensitive(true)
+                .build()
+                .expect("an escaped literal is a valid regex")
+        });
+
+        LiteralMatcher {
+            finder: memmem::Finder::new(needle).into_owned(),
+            folded,
+        }
+    }
+}
+
+impl Matcher for LiteralMatcher {
+    fn find_match_at(&self, haystack: &[u8], start: usize) -> Option<Match> {
+        let range = match &self.folded {
+            Some(regex) => regex.find_at(haystack, start).map(|m| m.range()),
+            None => self
+                .finder
+                .find(&haystack[start..])
+                .map(|i| start + i..start + i + self.finder.needle().len()),
+        }?;
+        Some(Match { pattern: 0, range })
+    }
+}
+
+/// Matches any of several fixed strings at once with Aho-Corasick. When several strings match at
+/// the same position, the one given first wins.
+///
+/// Case-insensitive matching only folds ASCII letters.
+pub struct LiteralSetMatcher {
+    automaton: AhoCorasick,
+}
+
+impl LiteralSetMatcher {
+    pub fn new(needles: &[impl AsRef<[u8]>], ascii_ignore_case: bool) -> LiteralSetMatcher {
+        let automaton = AhoCorasick::builder()
+            .match_kind(aho_corasick::MatchKind::LeftmostFirst)
+            .ascii_case_insensitive(ascii_ignore_case)
+            .build(needles)
+            .expect("the number of fixed strings fits in the automaton");
+
+        LiteralSetMatcher { automaton }
+    }
+}
+
+impl Matcher for LiteralSetMatcher {
+    fn find_match_at(&self, haystack: &[u8], start: usize) -> Option<Match> {
+        let input = aho_corasick::Input::new(haystack).span(start..haystack.len());
+        self.automaton.find(input).map(|m| Match {
+            pattern: m.pattern().as_usize(),
+            range: m.range(),
+        })
+    }
+}
+
+/// Matches any of one or more regular expressions.
+pub struct RegexMatcher {
+    regex: meta::Regex,
+}
+
+impl RegexMatcher {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<RegexMatcher, Box<meta::BuildError>> {
+        RegexMatcher::new_many(&[pattern], ignore_case)
+    }
+
+    pub fn new_many(
+        patterns: &[impl AsRef<str>],
+        ignore_case: bool,
+    ) -> Result<RegexMatcher, Box<meta::BuildError>> {
+        let regex = meta::Regex::builder()
+            .syntax(syntax::Config::new().case_insensitive(ignore_case))
+            .build_many(patterns)
+            .map_err(Box::new)?;
+
+        Ok(RegexMatcher { regex })
+    }
+}
+
+impl Matcher for RegexMatcher {
+    fn find_match_at(&self, haystack: &[u8], start: usize) -> Option<Match> {
+        let input = Input::new(haystack).span(start..haystack.len());
+        self.regex.find(input).map(|m| Match {
+            pattern: m.pattern().as_usize(),
+            range: m.range(),
+        })
+    }
+
+    fn interpolate(&self, haystack: &[u8], m: &Match, replacement: &[u8], dst: &mut Vec<u8>) {
+        // The leftmost match from the start of `m` is `m` itself.
+        let mut captures = self.regex.create_captures();
+        let input = Input::new(haystack).span(m.range.start..haystack.len());
+        self.regex.search_captures(&input, &mut captures);
+        captures.interpolate_bytes_into(haystack, replacement, dst);
+    }
+}
+
+/// Whether `pattern` contains an uppercase letter that it would match literally, which turns off
+/// smart case. Escapes and class names in regular expressions, like `\W` or `\p{Greek}`, do not
+/// count.
+pub fn has_uppercase(pattern: &str, regex: bool) -> bool {
+    if !regex {
+        return pattern.chars().any(char::is_uppercase);
+    }
+
+    struct UppercaseVisitor;
+
+    impl ast::Visitor for UppercaseVisitor {
+        type Output = bool;
+        type Err = ();
+
+        fn finish(self) -> Result<bool, ()> {
+            Ok(false)
+        }
+
+        fn visit_pre(&mut self, ast: &Ast) -> Result<(), ()> {
+            match ast {
+                Ast::Literal(literal) if literal.c.is_uppercase() => Err(()),
+                _ => Ok(()),
+            }
+        }
+
+        fn visit_class_set_item_pre(&mut self, item: &ast::ClassSetItem) -> Result<(), ()> {
+            let uppercase = match item {
+                ast::ClassSetItem::Literal(literal) => literal.c.is_uppercase(),
+                ast::ClassSetItem::Range(range) => {
+                    range.start.c.is_uppercase() || range.end.c.is_uppercase()
+                }
+                _ => false,
+            };
+            match uppercase {
+                true => Err(()),
+                false => Ok(()),
+            }
+        }
+    }
+
+    // The visitor stops with an error on the first uppercase literal. Invalid patterns are
+    // reported when the matcher is built.
+    match ast::parse::Parser::new().parse(pattern) {
+        Ok(ast) => ast::visit(&ast, UppercaseVisitor).is_err(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_is_not_a_pattern() {
+        let matcher = LiteralMatcher::new("a.c", false);
+
+        assert_eq!(Some(2..5), matcher.find(b"xya.c"));
+        assert!(!matcher.is_match(b"abc"));
+    }
+
+    #[test]
+    fn literal_ignore_case() {
+        let matcher = LiteralMatcher::new("rUsT", true);
+
+        assert_eq!(Some(1..5), matcher.find(b"Trust"));
+        assert_eq!(Some(6..10), matcher.find_at(b"RUST, rust", 1));
+    }
+
+    #[test]
+    fn unicode_simple_case_folding() {
+        // ẞ and ß fold together, but ß does not expand to "ss".
+        let matcher = LiteralMatcher::new("straße", true);
+        assert!(matcher.is_match("STRAẞE".as_bytes()));
+        assert!(!matcher.is_match(b"STRASSE"));
+
+        // Final and medial sigma both fold to σ.
+        let matcher = LiteralMatcher::new("ΟΔΟΣ", true);
+        assert_eq!(Some(0..8), matcher.find("οδος".as_bytes()));
+        assert_eq!(Some(0..8), matcher.find("οδοσ".as_bytes()));
+
+        // Without Turkish rules, dotless ı and dotted İ are not variants of i.
+        let matcher = LiteralMatcher::new("i", true);
+        assert!(matcher.is_match(b"I"));
+        assert!(!matcher.is_match("ı".as_bytes()));
+        assert!(!matcher.is_match("İ".as_bytes()));
+    }
+
+    #[test]
+    fn uppercase_patterns() {
+        assert!(has_uppercase("Rust", false));
+        assert!(has_uppercase("Ω", false));
+        // Fixed strings have no escapes.
+        assert!(has_uppercase(r"\W", false));
+
+        assert!(!has_uppercase(r"\W+\S[[:alpha:]]\p{Greek}", true));
+        assert!(has_uppercase(r"\w+Rust", true));
+        assert!(has_uppercase(r"[A-Z]", true));
+        assert!(!has_uppercase("(unclosed", true));
+    }
+
+    #[test]
+    fn regex_ignore_case() {
+        let matcher = RegexMatcher::new(r"^t\w+", true).unwrap();
+
+        assert_eq!(Some(0..5), matcher.find(b"Trust me."));
+        assert!(!matcher.is_match(b"Rust:"));
+    }
+
+    #[test]
+    fn find_all_matches() {
+        let matcher = RegexMatcher::new(r"o|x*", false).unwrap();
+        let matcher: &dyn Matcher = &matcher;
+
+        let matches: Vec<_> = matcher.find_iter(b"nb o").map(|m| m.range).collect();
+        assert_eq!(vec![0..0, 1..1, 2..2, 3..4, 4..4], matches);
+    }
+
+    #[test]
+    fn several_patterns() {
+        let matcher = LiteralSetMatcher::new(&["frog", "fro", "bog"], true);
+        let matcher: &dyn Matcher = &matcher;
+        let matches: Vec<_> = matcher.find_iter(b"a FROG, a bog").collect();
+        assert_eq!(
+            vec![
+                Match {
+                    pattern: 0,
+                    range: 2..6
+                },
+                Match {
+                    pattern: 2,
+                    range: 10..13
+                },
+            ],
+            matches
+        );
+
+        let matcher = RegexMatcher::new_many(&[r"\d+", r"[a-z]+"], false).unwrap();
+        let matcher: &dyn Matcher = &matcher;
+        let patterns: Vec<_> = matcher.find_iter(b"ab12c").map(|m| m.pattern).collect();
+        assert_eq!(vec![1, 0, 1], patterns);
+
+        assert!(!RegexMatcher::new_many(&[] as &[&str], false)
+            .unwrap()
+            .is_match(b"x"));
+    }
+
+    #[test]
+    fn replacements() {
+        let matcher = RegexMatcher::new(r"(\w+)@(?<host>\w+)", false).unwrap();
+        let matcher: &dyn Matcher = &matcher;
+        let mut replaced = Vec::new();
+        let count = matcher.replace_all(b"ann@home, bob@work", b"${host}:$1", &mut replaced);
+        assert_eq!(2, count);
+        assert_eq!(b"home:ann, work:bob", replaced.as_slice());
+
+        let matcher = LiteralSetMatcher::new(&["cat", "dog"], false);
+        let matcher: &dyn Matcher = &matcher;
+        let mut replaced = Vec::new();
+        matcher.replace_all(b"a cat and a dog", b"[$0$1]", &mut replaced);
+        assert_eq!(b"a [cat] and a [dog]", replaced.as_slice());
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(RegexMatcher::new("(unclosed", false).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::{self, Write};
+
+use serde_json::json;
+
+use crate::matcher::Matcher;
+
+const RESET: &[u8] = b"\x1b[0m";
+const FILE_NAME_COLOR: &[u8] = b"\x1b[35m";
+const LINE_NUMBER_COLOR: &[u8] = b"\x1b[32m";
+const SEPARATOR_COLOR: &[u8] = b"\x1b[36m";
+const MATCH_COLOR: &[u8] = b"\x1b[1;31m";
+
+/// How lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Text {
+        color: bool,
+    },
+    /// One JSON object per line.
+    Json,
+}
+
+/// Whether a line was selected or is printed as context around a selected line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Selected,
+    Context,
+}
+
+/// A line to print.
+pub(crate) struct Line<'l> {
+    pub index: usize,
+    /// Byte offset of the start of the line in the file.
+    pub offset: u64,
+    pub kind: Kind,
+    pub bytes: &'l [u8],
+}
+
+/// Writes selected and context lines, separating the groups of lines that are not contiguous.
+pub(crate) struct Printer<'a, W = &'a mut dyn Write> {
+    pub out: W,
+    pub matcher: &'a dyn Matcher,
+    /// The patterns of the matcher, in order.
+    pub patterns: &'a [String],
+    /// What matches are replaced with in selected lines, with `--replace`.
+    pub replacement: Option<&'a [u8]>,
+    pub format: Format,
+    /// The name of the searched file.
+    pub name: &'a str,
+    /// Whether text lines are prefixed with the name, when searching several files.
+    pub with_file_name: bool,
+    pub line_number: bool,
+    /// Whether to write `--` between groups, which is only done when printing context.
+    pub separate: bool,
+    pub last_printed: Option<usize>,
+}
+
+impl<W: Write> Printer<'_, W> {
+    pub fn print(&mut self, line: Line) -> io::Result<()> {
+        match self.format {
+            Format::Text { color } => self.print_text(line, color),
+            Format::Json => self.print_json(line),
+        }
+    }
+
+    /// Writes the name of the file, in color when enabled.
+    pub fn print_name(&mut self) -> io::Result<()> {
+        self.colored(FILE_NAME_COLOR, self.name.as_bytes())
+    }
+
+    fn print_text(&mut self, line: Line, color: bool) -> io::Result<()> {
+        if self.separate && self.last_printed.is_some_and(|last| line.index > last + 1) {
+            self.colored(SEPARATOR_COLOR, b"--")?;
+            self.out.write_all(b"\n")?;
+        }
+        self.last_printed = Some(line.index);
+
+        // Selected lines use `:` after their prefixes and context lines use `-`.
+        let separator: &[u8] = match line.kind {
+            Kind::Selected => b":",
+            Kind::Context => b"-",
+        };
+        if self.with_file_name {
+            self.print_name()?;
+            self.colored(SEPARATOR_COLOR, separator)?;
+        }
+        if self.line_number {
+            self.colored(LINE_NUMBER_COLOR, (line.index + 1).to_string().as_bytes())?;
+            self.colored(SEPARATOR_COLOR, separator)?;
+        }
+
+        if line.kind == Kind::Selected && (color || self.replacement.is_some()) {
+            let matcher = self.matcher;
+            let mut written = 0;
+            let mut replaced = Vec::new();
+            for found in matcher.find_iter(line.bytes) {
+                let text = match self.replacement {
+                    Some(replacement) => {
+                        replaced.clear();
+                        matcher.interpolate(line.bytes, &found, replacement, &mut replaced);
+                        &replaced[..]
+                    }
+                    None => &line.bytes[found.range.clone()],
+                };
+                self.out
+                    .write_all(&line.bytes[written..found.range.start])?;
+                if !text.is_empty() {
+                    self.colored(MATCH_COLOR, text)?;
+                }
+                written = found.range.end;
+            }
+            self.out.write_all(&line.bytes[written..])?;
+        } else {
+            self.out.write_all(line.bytes)?;
+        }
+        self.out.write_all(b"\n")
+    }
+
+    fn print_json(&mut self, line: Line) -> io::Result<()> {
+        let submatches: Vec<_> = match line.kind {
+            Kind::Selected => self
+                .matcher
+                .find_iter(line.bytes)
+                .filter(|m| !m.range.is_empty())
+                .map(|m| {
+                    let mut submatch = json!({
+                        "match": String::from_utf8_lossy(&line.bytes[m.range.clone()]),
+                        "start": m.range.start,
+                        "end": m.range.end,
+                        "pattern": self.patterns[m.pattern],
+                    });
+                    if let Some(replacement) = self.replacement {
+                        let mut replaced = Vec::new();
+                        self.matcher
+                            .interpolate(line.bytes, &m, replacement, &mut replaced);
+                        submatch["replacement"] = String::from_utf8_lossy(&replaced).into();
+                    }
+                    submatch
+                })
+                .collect(),
+            Kind::Context => Vec::new(),
+        };
+        let kind = match line.kind {
+            Kind::Selected => "match",
+            Kind::Context => "context",
+        };
+
+        let object = json!({
+            "type": kind,
+            "path": self.name,
+            "line_number": line.index + 1,
+            "absolute_offset": line.offset,
+            "line": String::from_utf8_lossy(line.bytes),
+            "submatches": submatches,
+        });
+        serde_json::to_writer(&mut self.out, &object)?;
+        self.out.write_all(b"\n")
+    }
+
+    fn colored(&mut self, color: &[u8], text: &[u8]) -> io::Result<()> {
+        if !matches!(self.format, Format::Text { color: true }) {
+            return self.out.write_all(text);
+        }
+        self.out.write_all(color)?;
+        self.out.write_all(text)?;
+        self.out.write_all(RESET)
+    }
+}
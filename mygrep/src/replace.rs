@@ -0,0 +1,71 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use tempfile::NamedTempFile;
+
+use crate::matcher::Matcher;
+use crate::Config;
+
+/// Replaces the matches in the selected lines of the file at `path` and writes a summary of the
+/// replacements to `out`. Returns the number of rewritten lines.
+///
+/// The new contents go to a temporary file next to the original, which is then renamed over it,
+/// so the file is never left half written. A symbolic link is resolved first, so the file it points
+/// to is rewritten and the link is kept. Binary files and files without matches are left alone.
+pub(crate) fn rewrite_file(
+    config: &Config,
+    matcher: &dyn Matcher,
+    path: &Path,
+    replacement: &[u8],
+    out: &mut Vec<u8>,
+) -> io::Result<usize> {
+    let contents = fs::read(path)?;
+    if memchr::memchr(0, &contents).is_some() {
+        return Ok(0);
+    }
+
+    let mut rewritten = Vec::with_capacity(contents.len());
+    let mut lines = 0;
+    let mut replacements = 0;
+    for line in contents.split_inclusive(|&b| b == b'\n') {
+        let text = crate::searcher::trim_line_terminator(line);
+        let reached_max = config.max_count.is_some_and(|max| lines >= max);
+        if reached_max || !matcher.is_match(text) {
+            rewritten.extend_from_slice(line);
+            continue;
+        }
+        lines += 1;
+        replacements += matcher.replace_all(text, replacement, &mut rewritten);
+        rewritten.extend_from_slice(&line[text.len()..]);
+    }
+    if lines == 0 || rewritten == contents {
+        return Ok(lines);
+    }
+
+    // Renaming over a symbolic link would replace the link itself with a regular file.
+    let target = fs::canonicalize(path)?;
+    let dir = target.parent().unwrap_or(Path::new("/"));
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(&rewritten)?;
+    file.as_file()
+        .set_permissions(fs::metadata(&target)?.permissions())?;
+    file.as_file().sync_all()?;
+    file.persist(&target).map_err(|e| e.error)?;
+
+    writeln!(
+        out,
+        "{}: {replacements} {} in {lines} {}",
+        path.display(),
+        plural(replacements, "replacement", "replacements"),
+        plural(lines, "line", "lines"),
+    )?;
+    Ok(lines)
+}
+
+fn plural<'a>(count: usize, one: &'a str, many: &'a str) -> &'a str {
+    match count {
+        1 => one,
+        _ => many,
+    }
+}
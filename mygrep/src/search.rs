@@ -0,0 +1,180 @@
+use std::io::{self, BufRead, Write};
+
+use crate::printer::{Format, Kind, Line, Printer};
+use crate::searcher::{Searcher, Sink, SinkLine};
+use crate::Config;
+
+/// The sink of the command line, which prints selected and context lines, or the counts and file
+/// names that replace them.
+pub(crate) struct StandardSink<'a, W = &'a mut dyn Write> {
+    config: &'a Config,
+    printer: Printer<'a, W>,
+    with_file_name: bool,
+}
+
+impl<'a, W: Write> StandardSink<'a, W> {
+    /// `name` is the name of the searched file, which prefixes lines when `with_file_name` is set.
+    pub fn new(
+        config: &'a Config,
+        searcher: &'a Searcher,
+        name: &'a str,
+        with_file_name: bool,
+        out: W,
+    ) -> StandardSink<'a, W> {
+        let (before_context, after_context) = searcher.context();
+        let format = match config.json {
+            true => Format::Json,
+            false => Format::Text {
+                color: config.color.enabled(),
+            },
+        };
+        let printer = Printer {
+            out,
+            matcher: searcher.matcher(),
+            patterns: &config.patterns,
+            replacement: config.replace.as_deref().map(str::as_bytes),
+            format,
+            name,
+            with_file_name,
+            line_number: config.line_number,
+            separate: before_context > 0 || after_context > 0,
+            last_printed: None,
+        };
+
+        StandardSink {
+            config,
+            printer,
+            with_file_name,
+        }
+    }
+
+    fn print(&mut self, line: &SinkLine, kind: Kind) -> io::Result<bool> {
+        self.printer.print(Line {
+            index: line.line_number - 1,
+            offset: line.offset,
+            kind,
+            bytes: line.bytes,
+        })?;
+        Ok(true)
+    }
+}
+
+impl<W: Write> Sink for StandardSink<'_, W> {
+    fn matched(&mut self, _searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+        let config = self.config;
+        if !lists_lines(config) {
+            // Listing files only needs to know whether something matched.
+            return Ok(!(config.files_with_matches || config.files_without_match || config.quiet));
+        }
+        self.print(line, Kind::Selected)
+    }
+
+    fn context(&mut self, _searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+        match lists_lines(self.config) {
+            true => self.print(line, Kind::Context),
+            false => Ok(true),
+        }
+    }
+
+    fn binary(&mut self, _searcher: &Searcher, _line: &SinkLine) -> io::Result<()> {
+        writeln!(
+            self.printer.out,
+            "Binary file {} matches",
+            self.printer.name
+        )
+    }
+
+    fn finish(&mut self, _searcher: &Searcher, selected: usize) -> io::Result<()> {
+        let config = self.config;
+        if config.quiet {
+            return Ok(());
+        }
+        if (config.files_with_matches && selected > 0)
+            || (config.files_without_match && selected == 0)
+        {
+            self.printer.print_name()?;
+            writeln!(self.printer.out)?;
+        } else if config.count {
+            if self.with_file_name {
+                self.printer.print_name()?;
+                write!(self.printer.out, ":")?;
+            }
+            writeln!(self.printer.out, "{selected}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A selected line printed once every file is searched, with `--sort`.
+pub(crate) struct RankedLine {
+    pub score: usize,
+    pub path: String,
+    pub line_number: usize,
+    pub output: Vec<u8>,
+}
+
+/// The sink of the command line with `--sort`, which keeps the printed selected lines to sort
+/// them.
+pub(crate) struct RankingSink<'a> {
+    /// Prints each selected line into its own buffer, which is taken once the line is printed.
+    sink: StandardSink<'a, Vec<u8>>,
+    name: &'a str,
+    pub lines: Vec<RankedLine>,
+}
+
+impl<'a> RankingSink<'a> {
+    pub fn new(
+        config: &'a Config,
+        searcher: &'a Searcher,
+        name: &'a str,
+        with_file_name: bool,
+    ) -> RankingSink<'a> {
+        RankingSink {
+            sink: StandardSink::new(config, searcher, name, with_file_name, Vec::new()),
+            name,
+            lines: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, searcher: &Searcher, line: &SinkLine) {
+        self.lines.push(RankedLine {
+            score: searcher.matcher().score(line.bytes).unwrap_or(0),
+            path: self.name.to_string(),
+            line_number: line.line_number,
+            output: std::mem::take(&mut self.sink.printer.out),
+        });
+    }
+}
+
+impl Sink for RankingSink<'_> {
+    fn matched(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+        let more = self.sink.matched(searcher, line)?;
+        self.push(searcher, line);
+        Ok(more)
+    }
+
+    fn binary(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<()> {
+        self.sink.binary(searcher, line)?;
+        self.push(searcher, line);
+        Ok(())
+    }
+}
+
+/// Searches the lines read from `reader` and writes the output for them to `out`, line by line.
+/// Returns the number of selected lines.
+pub(crate) fn search_reader(
+    config: &Config,
+    searcher: &Searcher,
+    name: &str,
+    with_file_name: bool,
+    reader: impl BufRead,
+    out: &mut dyn Write,
+) -> io::Result<usize> {
+    let sink = StandardSink::new(config, searcher, name, with_file_name, out);
+    searcher.search_reader(reader, sink)
+}
+
+/// Whether the output lists lines, as opposed to counts or file names.
+pub(crate) fn lists_lines(config: &Config) -> bool {
+    !(config.quiet || config.count || config.files_with_matches || config.files_without_match)
+}
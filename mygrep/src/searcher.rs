@@ -0,0 +1,459 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use memmap2::Mmap;
+
+use crate::matcher::Matcher;
+
+/// Binary files are detected by looking for a NUL byte in their first `BINARY_DETECTION_LEN`
+/// bytes.
+const BINARY_BYTE: u8 = 0;
+/// How much of the start of a file is checked for binary content. The first buffer of a memory
+/// map or a slice is the whole file, which would be read one more time otherwise.
+const BINARY_DETECTION_LEN: usize = 8 * 1024;
+
+/// Files at least this large are memory mapped instead of read.
+pub(crate) const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// What to do with binary files, which have a NUL byte near their start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinaryDetection {
+    /// Search binary files like text files.
+    #[default]
+    None,
+    /// Stop searching a binary file at its first selected line, which is passed to
+    /// [`Sink::binary`] instead of [`Sink::matched`].
+    Quit,
+}
+
+/// A line passed to a [`Sink`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkLine<'b> {
+    /// The line number, starting at 1.
+    pub line_number: usize,
+    /// Byte offset of the start of the line in the searched text.
+    pub offset: u64,
+    /// The line, without its line terminator.
+    pub bytes: &'b [u8],
+}
+
+/// Receives the results of a search, line by line.
+///
+/// The searcher stops reading when a callback returns `Ok(false)` or an error, which it returns.
+pub trait Sink {
+    /// Called for each selected line.
+    fn matched(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<bool>;
+
+    /// Called for each line of context before or after a selected line.
+    fn context(&mut self, _searcher: &Searcher, _line: &SinkLine) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Called with the first selected line of a binary file, with [`BinaryDetection::Quit`].
+    fn binary(&mut self, _searcher: &Searcher, _line: &SinkLine) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the search of a file is over, with the number of selected lines.
+    fn finish(&mut self, _searcher: &Searcher, _selected: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn matched(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+        (**self).matched(searcher, line)
+    }
+
+    fn context(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+        (**self).context(searcher, line)
+    }
+
+    fn binary(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<()> {
+        (**self).binary(searcher, line)
+    }
+
+    fn finish(&mut self, searcher: &Searcher, selected: usize) -> io::Result<()> {
+        (**self).finish(searcher, selected)
+    }
+}
+
+/// A sink that calls a closure with each selected line.
+pub struct Lines<F>(pub F);
+
+impl<F: FnMut(&SinkLine) -> io::Result<bool>> Sink for Lines<F> {
+    fn matched(&mut self, _searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+        (self.0)(line)
+    }
+}
+
+/// Configures a [`Searcher`].
+#[derive(Debug, Clone, Default)]
+pub struct SearcherBuilder {
+    before_context: usize,
+    after_context: usize,
+    max_count: Option<usize>,
+    invert_match: bool,
+    binary: BinaryDetection,
+    encoding: Option<&'static Encoding>,
+    bom_sniffing: bool,
+}
+
+impl SearcherBuilder {
+    pub fn new() -> SearcherBuilder {
+        SearcherBuilder::default()
+    }
+
+    /// The number of lines of context to pass to the sink before each selected line.
+    pub fn before_context(mut self, lines: usize) -> SearcherBuilder {
+        self.before_context = lines;
+        self
+    }
+
+    /// The number of lines of context to pass to the sink after each selected line.
+    pub fn after_context(mut self, lines: usize) -> SearcherBuilder {
+        self.after_context = lines;
+        self
+    }
+
+    /// Stop searching a file after this many selected lines, once their context is passed.
+    pub fn max_count(mut self, max: Option<usize>) -> SearcherBuilder {
+        self.max_count = max;
+        self
+    }
+
+    /// Select the lines that do not match instead.
+    pub fn invert_match(mut self, invert: bool) -> SearcherBuilder {
+        self.invert_match = invert;
+        self
+    }
+
+    pub fn binary_detection(mut self, binary: BinaryDetection) -> SearcherBuilder {
+        self.binary = binary;
+        self
+    }
+
+    /// Decode the searched text from `encoding` into UTF-8 before searching it. By default, text
+    /// is searched as is. Line offsets are then offsets in the decoded text.
+    pub fn encoding(mut self, encoding: Option<&'static Encoding>) -> SearcherBuilder {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Decode text that starts with a UTF-8 or UTF-16 byte order mark with the encoding of the
+    /// mark, even when another encoding is set. The mark itself is not searched.
+    pub fn bom_sniffing(mut self, yes: bool) -> SearcherBuilder {
+        self.bom_sniffing = yes;
+        self
+    }
+
+    pub fn build(self, matcher: Box<dyn Matcher>) -> Searcher {
+        Searcher {
+            matcher,
+            config: self,
+        }
+    }
+}
+
+/// Searches text line by line for the lines selected by a matcher, and passes them to a [`Sink`].
+///
+/// A searcher can be shared by threads that search different files.
+pub struct Searcher {
+    matcher: Box<dyn Matcher>,
+    config: SearcherBuilder,
+}
+
+impl Searcher {
+    pub fn matcher(&self) -> &dyn Matcher {
+        self.matcher.as_ref()
+    }
+
+    /// The number of lines of context before and after selected lines.
+    pub fn context(&self) -> (usize, usize) {
+        (self.config.before_context, self.config.after_context)
+    }
+
+    /// Searches the file at `path`, memory mapping it when it is large. Returns the number of
+    /// selected lines.
+    pub fn search_path(&self, path: &Path, sink: impl Sink) -> io::Result<usize> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        if len >= MMAP_THRESHOLD {
+            // SAFETY: the map is only read, and like other greps, a file that is modified while it
+            // is searched can give inconsistent results, but not undefined behavior for the reader.
+            let map = unsafe { Mmap::map(&file)? };
+            return self.search_reader(&map[..], sink);
+        }
+
+        let mut contents = Vec::with_capacity(len as usize);
+        file.read_to_end(&mut contents)?;
+        self.search_reader(&contents[..], sink)
+    }
+
+    pub fn search_slice(&self, contents: &[u8], sink: impl Sink) -> io::Result<usize> {
+        self.search_reader(contents, sink)
+    }
+
+    /// Searches the lines read from `reader`, as they are read. Returns the number of selected
+    /// lines.
+    pub fn search_reader(
+        &self,
+        mut reader: impl BufRead,
+        mut sink: impl Sink,
+    ) -> io::Result<usize> {
+        let config = &self.config;
+        let bom = config.bom_sniffing && Encoding::for_bom(reader.fill_buf()?).is_some();
+        let selected = match config.encoding.is_some() || bom {
+            true => {
+                let decoder = DecodeReaderBytesBuilder::new()
+                    .encoding(config.encoding)
+                    .bom_sniffing(config.bom_sniffing)
+                    .bom_override(config.bom_sniffing)
+                    .strip_bom(true)
+                    .build(reader);
+                self.search_lines(BufReader::new(decoder), &mut sink)?
+            }
+            // Text without a mark is searched as is rather than validated as UTF-8.
+            false => self.search_lines(reader, &mut sink)?,
+        };
+        sink.finish(self, selected)?;
+        Ok(selected)
+    }
+
+    fn search_lines(&self, mut reader: impl BufRead, sink: &mut impl Sink) -> io::Result<usize> {
+        let config = &self.config;
+        let binary = config.binary == BinaryDetection::Quit && {
+            let start = reader.fill_buf()?;
+            memchr::memchr(BINARY_BYTE, &start[..start.len().min(BINARY_DETECTION_LEN)]).is_some()
+        };
+        let mut selected = 0;
+        // Lines that may be passed as context before the next selected line.
+        let mut before: VecDeque<(usize, u64, Vec<u8>)> =
+            VecDeque::with_capacity(config.before_context);
+        let mut after_remaining = 0;
+        let mut buffer = Vec::new();
+        let mut next_offset = 0;
+
+        for line_number in 1.. {
+            buffer.clear();
+            let offset = next_offset;
+            match reader.read_until(b'\n', &mut buffer)? {
+                0 => break,
+                read => next_offset += read as u64,
+            }
+            let bytes = trim_line_terminator(&buffer);
+            let line = SinkLine {
+                line_number,
+                offset,
+                bytes,
+            };
+            let reached_max = config.max_count.is_some_and(|max| selected >= max);
+            if reached_max && after_remaining == 0 {
+                break;
+            }
+
+            if reached_max || self.matcher.is_match(bytes) == config.invert_match {
+                if after_remaining > 0 {
+                    if !sink.context(self, &line)? {
+                        break;
+                    }
+                    after_remaining -= 1;
+                } else if config.before_context > 0 && !binary {
+                    // Reuse the buffer of the line that falls out of the window.
+                    let mut saved = match before.len() == config.before_context {
+                        true => before
+                            .pop_front()
+                            .map(|(_, _, saved)| saved)
+                            .unwrap_or_default(),
+                        false => Vec::new(),
+                    };
+                    saved.clear();
+                    saved.extend_from_slice(bytes);
+                    before.push_back((line_number, offset, saved));
+                }
+                continue;
+            }
+            selected += 1;
+
+            if binary {
+                // Printing lines of a binary file would garble the terminal.
+                sink.binary(self, &line)?;
+                break;
+            }
+            for (line_number, offset, bytes) in before.drain(..) {
+                let line = SinkLine {
+                    line_number,
+                    offset,
+                    bytes: &bytes,
+                };
+                if !sink.context(self, &line)? {
+                    return Ok(selected);
+                }
+            }
+            if !sink.matched(self, &line)? {
+                break;
+            }
+            after_remaining = config.after_context;
+        }
+
+        Ok(selected)
+    }
+}
+
+/// Strips a trailing `\n` or `\r\n`, like `str::lines`.
+pub(crate) fn trim_line_terminator(line: &[u8]) -> &[u8] {
+    match line.strip_suffix(b"\n") {
+        Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+        None => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::LiteralMatcher;
+
+    /// Records every callback as a line of text.
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl Sink for Recorder {
+        fn matched(&mut self, _searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+            let text = String::from_utf8_lossy(line.bytes);
+            self.0
+                .push(format!("{}:{}:{text}", line.line_number, line.offset));
+            Ok(true)
+        }
+
+        fn context(&mut self, _searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+            let text = String::from_utf8_lossy(line.bytes);
+            self.0
+                .push(format!("{}-{}-{text}", line.line_number, line.offset));
+            Ok(true)
+        }
+
+        fn binary(&mut self, _searcher: &Searcher, line: &SinkLine) -> io::Result<()> {
+            self.0.push(format!("binary at {}", line.line_number));
+            Ok(())
+        }
+
+        fn finish(&mut self, _searcher: &Searcher, selected: usize) -> io::Result<()> {
+            self.0.push(format!("{selected} selected"));
+            Ok(())
+        }
+    }
+
+    fn searcher(builder: SearcherBuilder, needle: &str) -> Searcher {
+        builder.build(Box::new(LiteralMatcher::new(needle, false)))
+    }
+
+    #[test]
+    fn sink_callbacks() {
+        let searcher = searcher(
+            SearcherBuilder::new().before_context(1).after_context(1),
+            "b",
+        );
+        let mut recorder = Recorder::default();
+        let selected = searcher
+            .search_slice(b"a\nb\r\nc\nd\nb", &mut recorder)
+            .unwrap();
+
+        assert_eq!(2, selected);
+        assert_eq!(
+            vec!["1-0-a", "2:2:b", "3-5-c", "4-7-d", "5:9:b", "2 selected"],
+            recorder.0
+        );
+    }
+
+    #[test]
+    fn stop_from_the_sink() {
+        let searcher = searcher(SearcherBuilder::new(), "x");
+        let mut seen = Vec::new();
+        let selected = searcher
+            .search_slice(
+                b"x1\nx2\nx3\n",
+                Lines(|line: &SinkLine| {
+                    seen.push(line.line_number);
+                    Ok(line.line_number < 2)
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(2, selected);
+        assert_eq!(vec![1, 2], seen);
+    }
+
+    #[test]
+    fn binary_detection() {
+        let contents = b"a\0\nb\nb\n";
+        let mut recorder = Recorder::default();
+        let quit = searcher(
+            SearcherBuilder::new().binary_detection(BinaryDetection::Quit),
+            "b",
+        );
+        assert_eq!(1, quit.search_slice(contents, &mut recorder).unwrap());
+        assert_eq!(vec!["binary at 2", "1 selected"], recorder.0);
+
+        let text = searcher(SearcherBuilder::new(), "b");
+        assert_eq!(
+            2,
+            text.search_slice(contents, Lines(|_: &SinkLine| Ok(true)))
+                .unwrap()
+        );
+
+        // Only the start of the file is checked.
+        let mut contents = b"b\n".repeat(BINARY_DETECTION_LEN);
+        contents.extend_from_slice(b"\0\n");
+        let mut recorder = Recorder::default();
+        assert_eq!(
+            BINARY_DETECTION_LEN,
+            quit.search_slice(&contents, &mut recorder).unwrap()
+        );
+        assert!(!recorder.0.iter().any(|event| event.starts_with("binary")));
+    }
+
+    #[test]
+    fn decode_text() {
+        let searcher = searcher(
+            SearcherBuilder::new().encoding(Some(encoding_rs::WINDOWS_1252)),
+            "café",
+        );
+        let mut recorder = Recorder::default();
+        searcher
+            .search_slice(b"tea\ncaf\xe9 au lait\n", &mut recorder)
+            .unwrap();
+        assert_eq!(vec!["2:4:café au lait", "1 selected"], recorder.0);
+    }
+
+    #[test]
+    fn sniff_byte_order_marks() {
+        let utf16: Vec<u8> = "\u{feff}tea\ncafé au lait\n"
+            .encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+        let builder = SearcherBuilder::new().bom_sniffing(true);
+        let mut recorder = Recorder::default();
+        searcher(builder.clone(), "café")
+            .search_slice(&utf16, &mut recorder)
+            .unwrap();
+        assert_eq!(vec!["2:4:café au lait", "1 selected"], recorder.0);
+
+        // The mark takes precedence over the encoding, and is not part of the first line.
+        let mut recorder = Recorder::default();
+        searcher(builder.encoding(Some(encoding_rs::WINDOWS_1252)), "tea")
+            .search_slice(&utf16, &mut recorder)
+            .unwrap();
+        assert_eq!(vec!["1:0:tea", "1 selected"], recorder.0);
+
+        let mut recorder = Recorder::default();
+        searcher(SearcherBuilder::new(), "café")
+            .search_slice(&utf16, &mut recorder)
+            .unwrap();
+        assert_eq!(vec!["0 selected"], recorder.0);
+    }
+}
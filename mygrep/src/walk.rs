@@ -0,0 +1,250 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{DirEntry, WalkBuilder, WalkState};
+
+/// Which files to search under the paths given on the command line.
+#[derive(clap::Args)]
+pub struct WalkOptions {
+    /// Search directories recursively.
+    #[arg(short, long)]
+    pub recursive: bool,
+    /// Only search files whose name matches GLOB. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+    /// Skip files whose name matches GLOB. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+    /// Number of threads walking and searching files. A single thread visits files in order.
+    #[arg(short = 'j', long, value_name = "NUM", default_value_t = crate::default_threads())]
+    pub threads: usize,
+}
+
+/// Calls `visit` on every file to search, or on the error that prevented reaching it, until
+/// `visit` returns `false`.
+///
+/// Files named on the command line are always searched. Directories are only searched with
+/// `recursive`, skipping hidden entries, entries ignored by `.gitignore` or `.ignore` files, and
+/// files that do not pass the include and exclude globs.
+///
+/// With more than one thread, directories are walked by a work-stealing parallel walker whose
+/// threads also run `visit`, so files are searched as soon as they are found. A single thread
+/// visits files in order.
+pub fn visit<F>(paths: &[String], options: &WalkOptions, visit: F) -> Result<(), globset::Error>
+where
+    F: Fn(Result<&Path, ignore::Error>) -> bool + Sync,
+{
+    let filter = NameFilter::new(options)?;
+
+    let mut roots = Vec::new();
+    for path in paths {
+        if options.recursive || !Path::new(path).is_dir() {
+            roots.push(path);
+        } else if !visit(Err(is_a_directory(path))) {
+            return Ok(());
+        }
+    }
+    let Some((first, rest)) = roots.split_first() else {
+        return Ok(());
+    };
+    let mut builder = WalkBuilder::new(first);
+    for root in rest {
+        builder.add(root);
+    }
+    builder.require_git(false);
+
+    let visit_entry = |entry: Result<DirEntry, ignore::Error>| match entry {
+        // Named files are searched even when the globs do not match them.
+        Ok(entry)
+            if entry.file_type().is_some_and(|t| t.is_file())
+                && (entry.depth() == 0 || filter.is_wanted(entry.path())) =>
+        {
+            // Searching the current directory reports paths relative to it.
+            visit(Ok(entry.path().strip_prefix("./").unwrap_or(entry.path())))
+        }
+        Ok(_) => true,
+        Err(e) => visit(Err(e)),
+    };
+
+    if options.threads <= 1 {
+        let mut walker = builder.sort_by_file_name(|a, b| a.cmp(b)).build();
+        walker.try_for_each(|entry| visit_entry(entry).then_some(()));
+    } else {
+        builder.threads(options.threads).build_parallel().run(|| {
+            Box::new(|entry| match visit_entry(entry) {
+                true => WalkState::Continue,
+                false => WalkState::Quit,
+            })
+        });
+    }
+
+    Ok(())
+}
+
+fn is_a_directory(path: &str) -> ignore::Error {
+    ignore::Error::WithPath {
+        path: PathBuf::from(path),
+        err: Box::new(ignore::Error::Io(io::Error::other("Is a directory"))),
+    }
+}
+
+/// Filters walked files by name with the include and exclude globs.
+struct NameFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl NameFilter {
+    fn new(options: &WalkOptions) -> Result<NameFilter, globset::Error> {
+        let include = match options.include.is_empty() {
+            true => None,
+            false => Some(glob_set(&options.include)?),
+        };
+
+        Ok(NameFilter {
+            include,
+            exclude: glob_set(&options.exclude)?,
+        })
+    }
+
+    fn is_wanted(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default();
+        self.include.as_ref().is_none_or(|set| set.is_match(name)) && !self.exclude.is_match(name)
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Mutex;
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "a.rs",
+            "b.txt",
+            ".hidden.rs",
+            "sub/c.rs",
+            "target/d.rs",
+            "skip/e.rs",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        // Ignore files are honored even outside of a git repository.
+        fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+        fs::write(dir.path().join(".ignore"), "skip/\n").unwrap();
+        dir
+    }
+
+    fn files(paths: &[String], options: &WalkOptions) -> Vec<PathBuf> {
+        let found = Mutex::new(Vec::new());
+        visit(paths, options, |path| {
+            found.lock().unwrap().push(path.unwrap().to_path_buf());
+            true
+        })
+        .unwrap();
+        found.into_inner().unwrap()
+    }
+
+    fn names(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    fn options(include: &[&str], exclude: &[&str]) -> WalkOptions {
+        WalkOptions {
+            recursive: true,
+            include: include.iter().map(|glob| glob.to_string()).collect(),
+            exclude: exclude.iter().map(|glob| glob.to_string()).collect(),
+            threads: 1,
+        }
+    }
+
+    #[test]
+    fn recursive_walk_skips_hidden_and_ignored() {
+        let dir = tree();
+        let root = dir.path().display().to_string();
+
+        let found = files(&[root], &options(&[], &[]));
+        assert_eq!(vec!["a.rs", "b.txt", "sub/c.rs"], names(dir.path(), found));
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let dir = tree();
+        let root = dir.path().display().to_string();
+
+        let found = files(std::slice::from_ref(&root), &options(&["*.rs"], &[]));
+        assert_eq!(vec!["a.rs", "sub/c.rs"], names(dir.path(), found));
+
+        let found = files(&[root], &options(&["*.rs"], &["c.*"]));
+        assert_eq!(vec!["a.rs"], names(dir.path(), found));
+    }
+
+    #[test]
+    fn directories_need_recursive() {
+        let dir = tree();
+        let root = dir.path().display().to_string();
+        let hidden = dir.path().join(".hidden.rs").display().to_string();
+        let mut options = options(&["*.txt"], &[]);
+        options.recursive = false;
+
+        // Named files are searched even when hidden or excluded by the globs.
+        let found = Mutex::new(Vec::new());
+        visit(&[root, hidden], &options, |path| {
+            found
+                .lock()
+                .unwrap()
+                .push(path.map(Path::to_path_buf).map_err(|e| e.to_string()));
+            true
+        })
+        .unwrap();
+        let found = found.into_inner().unwrap();
+        assert_eq!(2, found.len());
+        assert!(found[0].as_ref().unwrap_err().ends_with(": Is a directory"));
+        assert!(found[1].as_ref().unwrap().ends_with(".hidden.rs"));
+    }
+
+    #[test]
+    fn parallel_visit_finds_the_same_files() {
+        let dir = tree();
+        let root = dir.path().display().to_string();
+        let mut options = options(&["*.rs"], &[]);
+        options.threads = 4;
+
+        let mut found = names(dir.path(), files(&[root], &options));
+        found.sort();
+        assert_eq!(vec!["a.rs", "sub/c.rs"], found);
+    }
+
+    #[test]
+    fn visit_stops_early() {
+        let dir = tree();
+        let root = dir.path().display().to_string();
+
+        let found = files(std::slice::from_ref(&root), &options(&[], &[]));
+        assert_eq!(3, found.len());
+
+        let count = Mutex::new(0);
+        visit(&[root], &options(&[], &[]), |_| {
+            *count.lock().unwrap() += 1;
+            false
+        })
+        .unwrap();
+        assert_eq!(1, count.into_inner().unwrap());
+    }
+}
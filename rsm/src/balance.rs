@@ -1,14 +1,20 @@
-use std::collections::BTreeMap;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 
+use codec::{Decode, DecodeAll, Encode};
 use num::{CheckedAdd, CheckedSub, Zero};
 use thiserror::Error;
 
+use crate::support::{Migrate, MigrationError, StorageVersion, Weight};
+
 #[derive(Debug, Error)]
 pub enum TransferError {
     #[error("Insufficient balance")]
     InsufficientBalance,
     #[error("Overflow when added to balance")]
     OverflowBalance,
+    #[error("Overflow when added to the free issuance")]
+    OverflowIssuance,
 }
 
 // Simple way to transform error to string implementing the Display trait:
@@ -30,29 +36,100 @@ pub enum TransferError {
 // }
 
 pub trait Config: crate::system::Config {
-    type Balance: Zero + CheckedSub + CheckedAdd + Copy;
+    type Balance: Zero + CheckedSub + CheckedAdd + Copy + PartialEq + Encode + Decode;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Pallet<T: Config> {
     balances: BTreeMap<T::AccountId, T::Balance>,
+    // The sum of every balance. Funds moved out of the pallet, like the ones bonded in staking,
+    // are not part of it. Added in storage version 1.
+    free_issuance: T::Balance,
 }
 
 impl<T: Config> Pallet<T> {
     pub fn new() -> Self {
         Self {
             balances: BTreeMap::new(),
+            free_issuance: T::Balance::zero(),
         }
     }
 
-    pub fn set_balance(&mut self, who: &T::AccountId, balance: T::Balance) {
-        self.balances.insert(who.clone(), balance);
+    pub fn set_balance(
+        &mut self,
+        who: &T::AccountId,
+        balance: T::Balance,
+    ) -> Result<(), TransferError> {
+        self.free_issuance = self
+            .free_issuance
+            .checked_sub(&self.balance(who))
+            .and_then(|issuance| issuance.checked_add(&balance))
+            .ok_or(TransferError::OverflowIssuance)?;
+        self.write_balance(who, balance);
+
+        Ok(())
+    }
+
+    /// Writes the balance of `who`, leaving the free issuance to the caller.
+    fn write_balance(&mut self, who: &T::AccountId, balance: T::Balance) {
+        if balance.is_zero() {
+            self.balances.remove(who);
+        } else {
+            self.balances.insert(who.clone(), balance);
+        }
     }
 
     pub fn balance(&self, who: &T::AccountId) -> T::Balance {
         *self.balances.get(who).unwrap_or(&T::Balance::zero())
     }
 
+    /// The sum of every balance, which leaves out the funds moved to other pallets.
+    pub fn free_issuance(&self) -> T::Balance {
+        self.free_issuance
+    }
+
+    /// Iterates over every account holding a balance.
+    pub fn balances(&self) -> impl Iterator<Item = (&T::AccountId, &T::Balance)> {
+        self.balances.iter()
+    }
+
+    pub fn deposit(&mut self, who: &T::AccountId, amount: T::Balance) -> Result<(), TransferError> {
+        let new_balance = self
+            .balance(who)
+            .checked_add(&amount)
+            .ok_or(TransferError::OverflowBalance)?;
+        let free_issuance = self
+            .free_issuance
+            .checked_add(&amount)
+            .ok_or(TransferError::OverflowIssuance)?;
+
+        self.write_balance(who, new_balance);
+        self.free_issuance = free_issuance;
+
+        Ok(())
+    }
+
+    pub fn withdraw(
+        &mut self,
+        who: &T::AccountId,
+        amount: T::Balance,
+    ) -> Result<(), TransferError> {
+        let new_balance = self
+            .balance(who)
+            .checked_sub(&amount)
+            .ok_or(TransferError::InsufficientBalance)?;
+        // Every balance is part of the free issuance.
+        let free_issuance = self
+            .free_issuance
+            .checked_sub(&amount)
+            .ok_or(TransferError::OverflowIssuance)?;
+
+        self.write_balance(who, new_balance);
+        self.free_issuance = free_issuance;
+
+        Ok(())
+    }
+
     pub fn transfer(
         &mut self,
         from: &T::AccountId,
@@ -69,16 +146,18 @@ impl<T: Config> Pallet<T> {
             .checked_add(&amount)
             .ok_or(TransferError::OverflowBalance)?;
 
-        self.set_balance(from, new_from_balance);
-        self.set_balance(to, new_to_balance);
+        // The free issuance stays the same.
+        self.write_balance(from, new_from_balance);
+        self.write_balance(to, new_to_balance);
 
         Ok(())
     }
 }
 
-pub enum Call<'a, T: Config> {
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum Call<T: Config> {
     Transfer {
-        to: &'a T::AccountId,
+        to: T::AccountId,
         amount: T::Balance,
     },
 }
@@ -88,7 +167,7 @@ where
     T::AccountId: 'a,
 {
     type Caller = &'a T::AccountId;
-    type Call = Call<'a, T>;
+    type Call = Call<T>;
 
     fn dispatch(
         &mut self,
@@ -96,14 +175,75 @@ where
         call: Self::Call,
     ) -> crate::support::DispatchResult {
         match call {
-            Call::Transfer { to, amount } => self.transfer(caller, to, amount)?,
+            Call::Transfer { to, amount } => self.transfer(caller, &to, amount)?,
+        }
+        Ok(())
+    }
+}
+
+impl<T: Config> Default for Pallet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Config> crate::support::Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> Migrate for Pallet<T> {
+    const STORAGE_VERSION: StorageVersion = 1;
+
+    fn migrate_from(
+        from: StorageVersion,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, Weight), MigrationError> {
+        match from {
+            // Version 1 tracks the free issuance, computed once from every stored balance.
+            0 => {
+                let v0::Pallet { balances } = v0::Pallet::<T>::decode_all(&mut &data[..])?;
+                let pallet = Pallet::<T> {
+                    free_issuance: total_of::<T>(&balances).ok_or(MigrationError::Corrupted(
+                        "balances overflow the free issuance".into(),
+                    ))?,
+                    balances,
+                };
+                Ok((pallet.encode(), pallet.balances.len() as Weight))
+            }
+            _ => Err(MigrationError::MissingMigration(from)),
+        }
+    }
+
+    fn post_upgrade(&self) -> Result<(), MigrationError> {
+        if Some(self.free_issuance) != total_of::<T>(&self.balances) {
+            return Err(MigrationError::PostUpgradeCheckFailed(
+                "free issuance does not match the balances",
+            ));
         }
         Ok(())
     }
 }
 
+/// The sum of `balances`, or `None` when it overflows.
+fn total_of<T: Config>(balances: &BTreeMap<T::AccountId, T::Balance>) -> Option<T::Balance> {
+    balances
+        .values()
+        .try_fold(T::Balance::zero(), |total, balance| {
+            total.checked_add(balance)
+        })
+}
+
+/// The storage layouts of older storage versions, decoded by the migrations.
+pub mod v0 {
+    use super::*;
+
+    #[derive(Decode)]
+    pub struct Pallet<T: Config> {
+        pub balances: BTreeMap<T::AccountId, T::Balance>,
+    }
+}
+
 #[cfg(test)]
 mod tets {
+    use crate::support::PalletStorage;
     use crate::system;
 
     use super::*;
@@ -128,7 +268,7 @@ mod tets {
     fn init_balance() {
         let (alice, bob, mut pallet) = setup();
 
-        pallet.set_balance(&alice, 100);
+        pallet.set_balance(&alice, 100).unwrap();
 
         assert_eq!(pallet.balance(&alice), 100);
         assert_eq!(pallet.balance(&bob), 0);
@@ -138,8 +278,8 @@ mod tets {
     fn transfer_balance() {
         let (alice, bob, mut pallet) = setup();
 
-        pallet.set_balance(&alice, 100);
-        pallet.set_balance(&bob, 100);
+        pallet.set_balance(&alice, 100).unwrap();
+        pallet.set_balance(&bob, 100).unwrap();
 
         pallet.transfer(&alice, &bob, 50).unwrap();
         assert_eq!(pallet.balance(&alice), 50);
@@ -150,8 +290,8 @@ mod tets {
     fn transfer_insufficient_balance() {
         let (alice, bob, mut pallet) = setup();
 
-        pallet.set_balance(&alice, 100);
-        pallet.set_balance(&bob, 100);
+        pallet.set_balance(&alice, 100).unwrap();
+        pallet.set_balance(&bob, 100).unwrap();
 
         let result = pallet.transfer(&alice, &bob, 150);
 
@@ -163,12 +303,144 @@ mod tets {
         assert_eq!(pallet.balance(&bob), 100);
     }
 
+    #[test]
+    fn deposit_and_withdraw_balance() {
+        let (alice, _, mut pallet) = setup();
+
+        pallet.deposit(&alice, 100).unwrap();
+        pallet.withdraw(&alice, 30).unwrap();
+        assert_eq!(pallet.balance(&alice), 70);
+
+        let result = pallet.withdraw(&alice, 71);
+        assert!(matches!(result, Err(TransferError::InsufficientBalance)));
+        assert_eq!(pallet.balance(&alice), 70);
+    }
+
+    #[test]
+    fn zero_balances_are_not_stored() {
+        let (alice, bob, mut pallet) = setup();
+
+        pallet.set_balance(&alice, 100).unwrap();
+        pallet.transfer(&alice, &bob, 100).unwrap();
+
+        assert_eq!(pallet.balance(&alice), 0);
+        assert!(!pallet.balances.contains_key(&alice));
+    }
+
+    #[test]
+    fn free_issuance_follows_balances() {
+        let (alice, bob, mut pallet) = setup();
+
+        pallet.set_balance(&alice, 100).unwrap();
+        pallet.deposit(&bob, 50).unwrap();
+        pallet.transfer(&alice, &bob, 30).unwrap();
+        pallet.withdraw(&bob, 20).unwrap();
+
+        assert_eq!(pallet.free_issuance(), 130);
+        assert!(pallet.post_upgrade().is_ok());
+
+        pallet.free_issuance = 0;
+        assert!(matches!(
+            pallet.post_upgrade(),
+            Err(MigrationError::PostUpgradeCheckFailed(_))
+        ));
+    }
+
+    #[test]
+    fn free_issuance_overflow() {
+        let (alice, bob, mut pallet) = setup();
+
+        pallet.set_balance(&alice, u128::MAX - 10).unwrap();
+
+        let result = pallet.deposit(&bob, 20);
+        assert!(matches!(result, Err(TransferError::OverflowIssuance)));
+        let result = pallet.set_balance(&bob, 20);
+        assert!(matches!(result, Err(TransferError::OverflowIssuance)));
+        assert_eq!(pallet.balance(&bob), 0);
+        assert_eq!(pallet.free_issuance(), u128::MAX - 10);
+
+        pallet.deposit(&bob, 10).unwrap();
+        assert_eq!(pallet.free_issuance(), u128::MAX);
+    }
+
+    #[test]
+    fn migrate_v0_computes_free_issuance() {
+        let (alice, bob, _) = setup();
+
+        // Version 0 only stored the balances.
+        let balances = BTreeMap::from([(alice.clone(), 30u128), (bob.clone(), 100)]);
+        let storage = PalletStorage {
+            version: 0,
+            data: balances.encode(),
+        };
+
+        let (pallet, weight) = Pallet::<TestConfig>::migrate(storage).unwrap();
+        assert_eq!(weight, 2);
+        assert_eq!(pallet.balance(&alice), 30);
+        assert_eq!(pallet.balance(&bob), 100);
+        assert_eq!(pallet.free_issuance(), 130);
+
+        // Up to date storage is decoded as is.
+        let (decoded, weight) = Pallet::<TestConfig>::migrate(pallet.store()).unwrap();
+        assert_eq!(weight, 0);
+        assert_eq!(decoded.free_issuance(), 130);
+    }
+
+    #[test]
+    fn migrate_rejects_overflowing_balances() {
+        let (alice, bob, _) = setup();
+
+        let balances = BTreeMap::from([(alice, u128::MAX), (bob, 1u128)]);
+        let storage = PalletStorage {
+            version: 0,
+            data: balances.encode(),
+        };
+        assert!(matches!(
+            Pallet::<TestConfig>::migrate(storage),
+            Err(MigrationError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn migrate_rejects_storage_of_another_version() {
+        let (alice, _, mut pallet) = setup();
+
+        // Version 1 storage, labelled as version 0.
+        pallet.set_balance(&alice, 100).unwrap();
+        let storage = PalletStorage {
+            version: 0,
+            data: pallet.encode(),
+        };
+        assert!(matches!(
+            Pallet::<TestConfig>::migrate(storage),
+            Err(MigrationError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn migrate_rejects_future_storage_version() {
+        let (_, _, pallet) = setup();
+
+        let storage = PalletStorage {
+            version: 2,
+            data: pallet.encode(),
+        };
+        assert!(matches!(
+            Pallet::<TestConfig>::migrate(storage),
+            Err(MigrationError::FutureStorageVersion {
+                found: 2,
+                supported: 1
+            })
+        ));
+    }
+
     #[test]
     fn transfer_overflow_balance() {
         let (alice, bob, mut pallet) = setup();
 
-        pallet.set_balance(&alice, u128::MAX);
-        pallet.set_balance(&bob, 100);
+        // The free issuance could not hold both balances, so they are stored directly.
+        pallet.write_balance(&alice, u128::MAX);
+        pallet.write_balance(&bob, 100);
 
         let result = pallet.transfer(&bob, &alice, 1);
 
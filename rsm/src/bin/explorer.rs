@@ -0,0 +1,238 @@
+//! A terminal block explorer over the database written by `rsm --db PATH`.
+//!
+//! Usage: `cargo run --features explorer --bin explorer -- PATH`
+//!
+//! Keys: ↑/↓ select a block, Tab switches between the accounts and claims views, `r` reloads the
+//! database and `q` quits.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+use rsm::db::{BlockRecord, ChainDb};
+use rsm::{system, Runtime};
+
+#[derive(Clone, Copy, PartialEq)]
+enum View {
+    Accounts,
+    Claims,
+}
+
+struct App {
+    db: ChainDb,
+    blocks: Vec<BlockRecord>,
+    state: Option<Runtime>,
+    selected: ListState,
+    view: View,
+}
+
+impl App {
+    fn new(db: ChainDb) -> Result<Self, Box<dyn Error>> {
+        let mut app = Self {
+            db,
+            blocks: Vec::new(),
+            state: None,
+            selected: ListState::default(),
+            view: View::Accounts,
+        };
+        app.reload()?;
+        Ok(app)
+    }
+
+    /// Reads the database again, keeping the selection on the latest block when it was there.
+    fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        let was_latest = self
+            .selected
+            .selected()
+            .is_none_or(|i| i + 1 >= self.blocks.len());
+        self.blocks = self.db.blocks()?;
+        self.state = self.db.state()?;
+        if was_latest && !self.blocks.is_empty() {
+            self.selected.select(Some(self.blocks.len() - 1));
+        }
+        Ok(())
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('r') => self.reload()?,
+                KeyCode::Up => self.selected.select_previous(),
+                KeyCode::Down => self.selected.select_next(),
+                KeyCode::Tab => {
+                    self.view = match self.view {
+                        View::Accounts => View::Claims,
+                        View::Claims => View::Accounts,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [blocks, details] =
+            Layout::horizontal([Constraint::Length(16), Constraint::Min(0)]).areas(main);
+        let [extrinsics, state] =
+            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(details);
+
+        self.draw_blocks(frame, blocks);
+        self.draw_extrinsics(frame, extrinsics);
+        self.draw_state(frame, state);
+        frame.render_widget(
+            Line::from(format!(
+                " {} | ↑/↓ select block  Tab switch view  r reload  q quit",
+                self.db.path().display()
+            ))
+            .dim(),
+            help,
+        );
+    }
+
+    fn draw_blocks(&mut self, frame: &mut Frame, area: Rect) {
+        let items = self
+            .blocks
+            .iter()
+            .map(|record| ListItem::new(format!("#{}", record.block.header.block_number)));
+        let list = List::new(items)
+            .block(Block::bordered().title(" Blocks "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.selected);
+    }
+
+    fn draw_extrinsics(&self, frame: &mut Frame, area: Rect) {
+        let Some(record) = self.selected.selected().and_then(|i| self.blocks.get(i)) else {
+            let empty = Paragraph::new("No blocks imported yet")
+                .block(Block::bordered().title(" Extrinsics "));
+            frame.render_widget(empty, area);
+            return;
+        };
+
+        let rows = record
+            .block
+            .extrinsics
+            .iter()
+            .enumerate()
+            .map(|(index, extrinsic)| {
+                let outcome = record.events.iter().find_map(|event| match event {
+                    system::Event::ExtrinsicSuccess { index: i } if *i == index as u32 => {
+                        Some(Ok(()))
+                    }
+                    system::Event::ExtrinsicFailed { index: i, error } if *i == index as u32 => {
+                        Some(Err(error.as_str()))
+                    }
+                    _ => None,
+                });
+                let (status, style) = match outcome {
+                    Some(Ok(())) => ("✓".to_string(), Style::new().fg(Color::Green)),
+                    Some(Err(error)) => (format!("✗ {error}"), Style::new().fg(Color::Red)),
+                    None => ("?".to_string(), Style::new()),
+                };
+                Row::new(vec![
+                    index.to_string(),
+                    extrinsic.caller.clone(),
+                    format!("{:?}", extrinsic.call),
+                    status,
+                ])
+                .style(style)
+            });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(3),
+                Constraint::Length(10),
+                Constraint::Min(20),
+                Constraint::Length(32),
+            ],
+        )
+        .header(Row::new(["#", "Caller", "Call", "Result"]).bold())
+        .block(Block::bordered().title(format!(
+            " Extrinsics of block #{} ",
+            record.block.header.block_number
+        )));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_state(&self, frame: &mut Frame, area: Rect) {
+        let [tabs, content] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(area);
+        let selected = match self.view {
+            View::Accounts => 0,
+            View::Claims => 1,
+        };
+        frame.render_widget(
+            Tabs::new(["Accounts", "Claims"])
+                .select(selected)
+                .highlight_style(Style::new().bold().reversed()),
+            tabs,
+        );
+
+        let block = Block::bordered().title(" Latest state ");
+        let Some(state) = &self.state else {
+            frame.render_widget(
+                Paragraph::new("No state imported yet").block(block),
+                content,
+            );
+            return;
+        };
+
+        let table = match self.view {
+            View::Accounts => {
+                let accounts = state
+                    .balance
+                    .balances()
+                    .map(|(who, _)| who)
+                    .chain(state.system.nonces().map(|(who, _)| who))
+                    .collect::<BTreeSet<_>>();
+                let rows = accounts.into_iter().map(|who| {
+                    Row::new(vec![
+                        who.clone(),
+                        state.balance.balance(who).to_string(),
+                        state.staking.bonded(who).to_string(),
+                        state.system.get_nonce(who).to_string(),
+                    ])
+                });
+                Table::new(rows, [Constraint::Ratio(1, 4); 4])
+                    .header(Row::new(["Account", "Free", "Bonded", "Nonce"]).bold())
+            }
+            View::Claims => {
+                let rows = state
+                    .proof_of_existence
+                    .claims()
+                    .map(|(claim, owner)| Row::new(vec![claim.clone(), owner.clone()]));
+                Table::new(rows, [Constraint::Ratio(1, 2); 2])
+                    .header(Row::new(["Claim", "Owner"]).bold())
+            }
+        };
+        frame.render_widget(table.block(block), content);
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or("Usage: explorer <database path>")?;
+    let app = App::new(ChainDb::open(path)?)?;
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,191 @@
+//! A simple on-disk database of the chain, written by the node and read by tools like the block
+//! explorer.
+//!
+//! Every imported block is kept in its own file under `blocks/`, along with the events the runtime
+//! deposited while executing it. The `state` file holds the encoded runtime state after the latest
+//! block, which is upgraded by the runtime when it was written by an older one.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use codec::{Decode, Encode};
+use thiserror::Error;
+
+use crate::{support, system, types, Runtime, State};
+
+#[derive(Debug, Error)]
+pub enum DbError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Corrupted database file {}: {source}", path.display())]
+    Corrupted { path: PathBuf, source: codec::Error },
+    #[error("Cannot upgrade the stored state: {0}")]
+    Upgrade(#[from] support::DispatchError),
+}
+
+/// An imported block and the events its execution produced.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct BlockRecord {
+    pub block: types::Block,
+    pub events: Vec<system::Event>,
+}
+
+pub struct ChainDb {
+    path: PathBuf,
+}
+
+impl ChainDb {
+    /// Opens the database at `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DbError> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(path.join("blocks"))?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The state after the latest imported block, if any block was imported, upgraded to the
+    /// storage layouts of the current runtime.
+    pub fn state(&self) -> Result<Option<Runtime>, DbError> {
+        let path = self.path.join("state");
+        let state: State = match fs::read(&path) {
+            Ok(bytes) => decode(&path, &bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let (runtime, _) = Runtime::on_runtime_upgrade(state)?;
+        Ok(Some(runtime))
+    }
+
+    /// Stores `block`, with the events in `state`, followed by the state it produced.
+    pub fn import(&self, block: types::Block, state: &Runtime) -> Result<(), DbError> {
+        let record = BlockRecord {
+            events: state.system.events().to_vec(),
+            block,
+        };
+        let file_name = format!("{:020}", record.block.header.block_number);
+
+        write_atomically(&self.path.join("blocks").join(file_name), &record.encode())?;
+        write_atomically(&self.path.join("state"), &state.state().encode())?;
+
+        Ok(())
+    }
+
+    /// Every imported block, in ascending block number order.
+    pub fn blocks(&self) -> Result<Vec<BlockRecord>, DbError> {
+        let mut paths = fs::read_dir(self.path.join("blocks"))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .filter(|path| !matches!(path, Ok(path) if path.extension().is_some()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+
+        paths
+            .iter()
+            .map(|path| decode(path, &fs::read(path)?))
+            .collect()
+    }
+}
+
+fn decode<T: Decode>(path: &Path, mut bytes: &[u8]) -> Result<T, DbError> {
+    T::decode(&mut bytes).map_err(|source| DbError::Corrupted {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Writes to a temporary file first, so readers never see a partially written file.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{balance, RuntimeCall};
+
+    fn transfer_block(block_number: types::BlockNumber, amount: types::Balance) -> types::Block {
+        types::Block {
+            header: support::Header { block_number },
+            extrinsics: vec![support::Extrinsic {
+                caller: String::from("Alice"),
+                call: RuntimeCall::Balances(balance::Call::Transfer {
+                    to: String::from("Bob"),
+                    amount,
+                }),
+            }],
+        }
+    }
+
+    #[test]
+    fn import_blocks_and_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ChainDb::open(dir.path()).unwrap();
+        assert!(db.state().unwrap().is_none());
+        assert!(db.blocks().unwrap().is_empty());
+
+        let mut runtime = Runtime::new();
+        runtime
+            .balance
+            .set_balance(&String::from("Alice"), 10)
+            .unwrap();
+        for (block_number, amount) in [(1, 5), (2, 50)] {
+            let block = transfer_block(block_number, amount);
+            runtime.execute_block(block.clone()).unwrap();
+            db.import(block, &runtime).unwrap();
+        }
+
+        let db = ChainDb::open(dir.path()).unwrap();
+        let state = db.state().unwrap().unwrap();
+        assert_eq!(state.system.block_number(), 2);
+        assert_eq!(state.balance.balance(&String::from("Bob")), 5);
+
+        let blocks = db.blocks().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].block.header.block_number, 1);
+        assert_eq!(
+            blocks[0].events,
+            vec![system::Event::ExtrinsicSuccess { index: 0 }]
+        );
+        assert_eq!(
+            blocks[1].events,
+            vec![system::Event::ExtrinsicFailed {
+                index: 0,
+                error: String::from("Insufficient balance"),
+            }]
+        );
+    }
+
+    #[test]
+    fn older_state_is_upgraded() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ChainDb::open(dir.path()).unwrap();
+        let alice = String::from("Alice");
+
+        // The balance storage as version 0 wrote it, without the free issuance.
+        let mut state = Runtime::new().state();
+        state.balance = support::PalletStorage {
+            version: 0,
+            data: std::collections::BTreeMap::from([(alice.clone(), 70u128)]).encode(),
+        };
+        fs::write(dir.path().join("state"), state.encode()).unwrap();
+
+        let runtime = db.state().unwrap().unwrap();
+        assert_eq!(runtime.balance.balance(&alice), 70);
+        assert_eq!(runtime.balance.free_issuance(), 70);
+    }
+
+    #[test]
+    fn corrupted_files_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ChainDb::open(dir.path()).unwrap();
+        fs::write(dir.path().join("state"), b"garbage").unwrap();
+
+        let err = db.state().unwrap_err();
+        assert!(matches!(err, DbError::Corrupted { .. }));
+    }
+}
@@ -0,0 +1,314 @@
+//! Executes the WebAssembly build of the runtime with wasmtime.
+//!
+//! The executor owns the storage and loads the runtime code from it before every block, so a
+//! `sudo::Call::SetCode` included in a block swaps the logic used for the following blocks
+//! without rebuilding the node.
+
+use std::collections::BTreeMap;
+
+use codec::{Decode, Encode};
+use thiserror::Error;
+use wasmtime::{
+    AsContext, AsContextMut, Caller, Engine, Extern, Instance, Linker, Memory, Module, Store,
+    TypedFunc,
+};
+
+use crate::support::{CODE_KEY, STATE_KEY};
+use crate::{support, types, Runtime, State};
+
+/// Returned by `ext_storage_get` when there is no value under the key.
+const NO_VALUE: u64 = u64::MAX;
+
+#[derive(Debug, Error)]
+pub enum ExecutorError {
+    #[error("No runtime code in storage")]
+    MissingCode,
+    #[error("Wasm execution failed: {0}")]
+    Wasm(wasmtime::Error),
+    #[error("Invalid data returned by the runtime: {0}")]
+    InvalidOutput(#[from] codec::Error),
+    #[error("Block execution failed: {0}")]
+    BlockFailed(String),
+    #[error("Cannot upgrade the runtime state: {0}")]
+    Upgrade(#[from] support::DispatchError),
+}
+
+/// The key-value storage kept by the host on behalf of the runtime.
+pub type Storage = BTreeMap<Vec<u8>, Vec<u8>>;
+
+pub struct Executor {
+    engine: Engine,
+    storage: Storage,
+    /// The last compiled runtime, along with the code it was compiled from.
+    module: Option<(Vec<u8>, Module)>,
+}
+
+impl Executor {
+    /// Creates an executor over `storage`, which must hold the runtime code under `CODE_KEY`.
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            engine: Engine::default(),
+            storage,
+            module: None,
+        }
+    }
+
+    /// Creates an executor for a new chain running `code`, starting from the `genesis` state.
+    pub fn with_genesis(code: Vec<u8>, genesis: &Runtime) -> Self {
+        let storage = Storage::from([
+            (CODE_KEY.to_vec(), code),
+            (STATE_KEY.to_vec(), genesis.state().encode()),
+        ]);
+        Self::new(storage)
+    }
+
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    /// Decodes the state written by the runtime, upgraded to the storage layouts of the native
+    /// runtime when the WebAssembly one is older.
+    pub fn state(&self) -> Result<Option<Runtime>, ExecutorError> {
+        let Some(state) = self.storage.get(STATE_KEY) else {
+            return Ok(None);
+        };
+        let state = State::decode(&mut state.as_slice())?;
+        let (runtime, _) = Runtime::on_runtime_upgrade(state)?;
+        Ok(Some(runtime))
+    }
+
+    pub fn execute_block(&mut self, block: &types::Block) -> Result<(), ExecutorError> {
+        let output = self.call("rsm_execute_block", &block.encode())?;
+        Result::<(), String>::decode(&mut output.as_slice())?.map_err(ExecutorError::BlockFailed)
+    }
+
+    /// Calls the runtime export `name` with `input`, returning the buffer it produced.
+    fn call(&mut self, name: &str, input: &[u8]) -> Result<Vec<u8>, ExecutorError> {
+        let module = self.module()?;
+        let mut store = Store::new(&self.engine, std::mem::take(&mut self.storage));
+        let output = Self::call_in(&mut store, &module, name, input);
+        self.storage = store.into_data();
+        output.map_err(ExecutorError::Wasm)
+    }
+
+    /// Compiles the code in storage, reusing the previous module if the code did not change.
+    fn module(&mut self) -> Result<Module, ExecutorError> {
+        let code = self
+            .storage
+            .get(CODE_KEY)
+            .ok_or(ExecutorError::MissingCode)?;
+        match &self.module {
+            Some((compiled, module)) if compiled == code => Ok(module.clone()),
+            _ => {
+                let module = Module::new(&self.engine, code).map_err(ExecutorError::Wasm)?;
+                self.module = Some((code.clone(), module.clone()));
+                Ok(module)
+            }
+        }
+    }
+
+    fn call_in(
+        store: &mut Store<Storage>,
+        module: &Module,
+        name: &str,
+        input: &[u8],
+    ) -> wasmtime::Result<Vec<u8>> {
+        let mut linker = Linker::new(store.engine());
+        linker.func_wrap(
+            "env",
+            "ext_storage_get",
+            |mut caller: Caller<'_, Storage>, key_ptr: u32, key_len: u32| {
+                let exports = Exports::of_caller(&mut caller)?;
+                let key = exports.read(&caller, key_ptr, key_len)?;
+                match caller.data().get(&key).cloned() {
+                    Some(value) => exports.write(&mut caller, &value),
+                    None => Ok(NO_VALUE),
+                }
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "ext_storage_set",
+            |mut caller: Caller<'_, Storage>,
+             key_ptr: u32,
+             key_len: u32,
+             value_ptr: u32,
+             value_len: u32| {
+                let exports = Exports::of_caller(&mut caller)?;
+                let key = exports.read(&caller, key_ptr, key_len)?;
+                let value = exports.read(&caller, value_ptr, value_len)?;
+                caller.data_mut().insert(key, value);
+                Ok(())
+            },
+        )?;
+
+        let instance = linker.instantiate(&mut *store, module)?;
+        let exports = Exports::of_instance(&mut *store, instance)?;
+        let packed = exports.write(&mut *store, input)?;
+        let output = instance
+            .get_typed_func::<(u32, u32), u64>(&mut *store, name)?
+            .call(&mut *store, unpack(packed))?;
+        let (ptr, len) = unpack(output);
+        exports.read(&*store, ptr, len)
+    }
+}
+
+fn unpack(packed: u64) -> (u32, u32) {
+    ((packed >> 32) as u32, packed as u32)
+}
+
+/// The exports of a runtime instance used to exchange buffers with it.
+struct Exports {
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+}
+
+impl Exports {
+    fn of_instance(mut store: impl AsContextMut, instance: Instance) -> wasmtime::Result<Self> {
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("runtime does not export its memory"))?;
+        let alloc = instance.get_typed_func(&mut store, "rsm_alloc")?;
+        Ok(Self { memory, alloc })
+    }
+
+    fn of_caller(caller: &mut Caller<'_, Storage>) -> wasmtime::Result<Self> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .ok_or_else(|| wasmtime::Error::msg("runtime does not export its memory"))?;
+        let alloc = caller
+            .get_export("rsm_alloc")
+            .and_then(Extern::into_func)
+            .ok_or_else(|| wasmtime::Error::msg("runtime does not export rsm_alloc"))?
+            .typed(&caller)?;
+        Ok(Self { memory, alloc })
+    }
+
+    fn read(&self, store: impl AsContext, ptr: u32, len: u32) -> wasmtime::Result<Vec<u8>> {
+        let mut buffer = vec![0; len as usize];
+        self.memory.read(&store, ptr as usize, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Copies `data` into memory allocated by the runtime, returning its packed location.
+    fn write(&self, mut store: impl AsContextMut, data: &[u8]) -> wasmtime::Result<u64> {
+        let ptr = self.alloc.call(&mut store, data.len() as u32)?;
+        self.memory.write(&mut store, ptr as usize, data)?;
+        Ok(((ptr as u64) << 32) | data.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bump allocator shared by the test runtimes, starting after their data segments.
+    const ALLOC: &str = r#"
+        (global $next (mut i32) (i32.const 1024))
+        (func (export "rsm_alloc") (param $len i32) (result i32)
+          (global.get $next)
+          (global.set $next (i32.add (global.get $next) (local.get $len))))
+    "#;
+
+    // Replaces its own code with the input it receives and returns `Ok(())`.
+    fn set_code_runtime() -> String {
+        format!(
+            r#"(module
+              (import "env" "ext_storage_set" (func $set (param i32 i32 i32 i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) ":code")
+              (data (i32.const 16) "\00")
+              {ALLOC}
+              (func (export "rsm_execute_block") (param $ptr i32) (param $len i32) (result i64)
+                (call $set (i32.const 0) (i32.const 5) (local.get $ptr) (local.get $len))
+                (i64.const 0x0000001000000001)))"#
+        )
+    }
+
+    // Stores "first" under `:state` if it is empty, otherwise copies it under `:copy`.
+    fn state_runtime() -> String {
+        format!(
+            r#"(module
+              (import "env" "ext_storage_get" (func $get (param i32 i32) (result i64)))
+              (import "env" "ext_storage_set" (func $set (param i32 i32 i32 i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) ":state")
+              (data (i32.const 16) "\00")
+              (data (i32.const 32) "first")
+              (data (i32.const 48) ":copy")
+              {ALLOC}
+              (func (export "rsm_execute_block") (param i32 i32) (result i64)
+                (local $value i64)
+                (local.set $value (call $get (i32.const 0) (i32.const 6)))
+                (if (i64.eq (local.get $value) (i64.const -1))
+                  (then (call $set (i32.const 0) (i32.const 6) (i32.const 32) (i32.const 5)))
+                  (else (call $set (i32.const 48) (i32.const 5)
+                    (i32.wrap_i64 (i64.shr_u (local.get $value) (i64.const 32)))
+                    (i32.wrap_i64 (local.get $value)))))
+                (i64.const 0x0000001000000001)))"#
+        )
+    }
+
+    // Fails every block with the message "boom".
+    fn failing_runtime() -> String {
+        format!(
+            r#"(module
+              (memory (export "memory") 1)
+              (data (i32.const 16) "\01\10boom")
+              {ALLOC}
+              (func (export "rsm_execute_block") (param i32 i32) (result i64)
+                (i64.const 0x0000001000000006)))"#
+        )
+    }
+
+    fn empty_block(block_number: types::BlockNumber) -> types::Block {
+        types::Block {
+            header: crate::support::Header { block_number },
+            extrinsics: vec![],
+        }
+    }
+
+    #[test]
+    fn runtime_reads_and_writes_host_storage() {
+        let storage = Storage::from([(CODE_KEY.to_vec(), state_runtime().into_bytes())]);
+        let mut executor = Executor::new(storage);
+
+        executor.execute_block(&empty_block(1)).unwrap();
+        assert_eq!(executor.storage().get(STATE_KEY), Some(&b"first".to_vec()));
+        assert_eq!(executor.storage().get(&b":copy"[..]), None);
+
+        executor.execute_block(&empty_block(2)).unwrap();
+        assert_eq!(
+            executor.storage().get(&b":copy"[..]),
+            Some(&b"first".to_vec())
+        );
+    }
+
+    #[test]
+    fn runtime_can_replace_its_code() {
+        let storage = Storage::from([(CODE_KEY.to_vec(), set_code_runtime().into_bytes())]);
+        let mut executor = Executor::new(storage);
+
+        let new_code = state_runtime().into_bytes();
+        executor.call("rsm_execute_block", &new_code).unwrap();
+        assert_eq!(executor.storage().get(CODE_KEY), Some(&new_code));
+
+        // The following block runs the new code.
+        executor.execute_block(&empty_block(1)).unwrap();
+        assert_eq!(executor.storage().get(STATE_KEY), Some(&b"first".to_vec()));
+    }
+
+    #[test]
+    fn block_errors_are_reported() {
+        let storage = Storage::from([(CODE_KEY.to_vec(), failing_runtime().into_bytes())]);
+        let mut executor = Executor::new(storage);
+
+        let err = executor.execute_block(&empty_block(1)).unwrap_err();
+        assert!(matches!(err, ExecutorError::BlockFailed(message) if message == "boom"));
+
+        let mut executor = Executor::new(Storage::new());
+        let err = executor.execute_block(&empty_block(1)).unwrap_err();
+        assert!(matches!(err, ExecutorError::MissingCode));
+    }
+}
@@ -0,0 +1,386 @@
+//! The runtime of a simple blockchain state machine.
+//!
+//! Besides running natively, the runtime compiles to WebAssembly without the standard library,
+//! to be executed by a host such as `executor::Executor`:
+//!
+//! ```sh
+//! cargo build --lib --release --target wasm32-unknown-unknown --no-default-features
+//! cargo run --features executor -- --wasm target/wasm32-unknown-unknown/release/rsm.wasm
+//! ```
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod balance;
+#[cfg(feature = "std")]
+pub mod db;
+pub mod proof_of_existence;
+pub mod staking;
+pub mod sudo;
+pub mod support;
+pub mod system;
+#[cfg(all(target_arch = "wasm32", not(feature = "std")))]
+mod wasm;
+
+#[cfg(feature = "executor")]
+pub mod executor;
+pub mod types {
+    use alloc::string::String;
+
+    use crate::support;
+
+    pub type AccountId = String;
+    pub type Balance = u128;
+    pub type BlockNumber = u128;
+    pub type Nonce = u32;
+    pub type Extrinsic = support::Extrinsic<AccountId, crate::RuntimeCall>;
+    pub type Header = support::Header<BlockNumber>;
+    pub type Block = support::Block<Header, Extrinsic>;
+    pub type Content = String;
+}
+
+use alloc::string::ToString;
+
+use codec::{Decode, Encode};
+use support::{Dispatch, Hooks, Migrate};
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum RuntimeCall {
+    Balances(balance::Call<Runtime>),
+    ProofOfExistence(proof_of_existence::Call<Runtime>),
+    Staking(staking::Call<Runtime>),
+    Sudo(sudo::Call<Runtime>),
+}
+
+impl system::Config for Runtime {
+    type AccountId = types::AccountId;
+    type Nonce = types::Nonce;
+    type BlockNumber = types::BlockNumber;
+
+    const MAX_BLOCK_WEIGHT: support::Weight = 1_000;
+    const EXTRINSIC_BASE_WEIGHT: support::Weight = 10;
+}
+
+impl balance::Config for Runtime {
+    type Balance = types::Balance;
+}
+
+impl proof_of_existence::Config for Runtime {
+    type Content = types::Content;
+}
+
+impl staking::Config for Runtime {
+    const ERA_LENGTH: types::BlockNumber = 10;
+    const BONDING_DURATION: types::BlockNumber = 20;
+    const MAX_UNLOCKING_CHUNKS: usize = 32;
+    const VALIDATOR_COUNT: usize = 4;
+    const ERA_REWARD: types::Balance = 1_000;
+}
+
+impl sudo::Config for Runtime {}
+
+/// The version of the runtime logic. Bump `spec_version` whenever a pallet storage layout changes,
+/// along with the `STORAGE_VERSION` of the pallet, whose migrations run the next time the runtime
+/// starts on existing state.
+pub const VERSION: support::RuntimeVersion = support::RuntimeVersion {
+    spec_name: "rsm",
+    spec_version: 2,
+};
+
+#[derive(Debug, Clone)]
+pub struct Runtime {
+    pub system: system::Pallet<Runtime>,
+    pub balance: balance::Pallet<Runtime>,
+    pub proof_of_existence: proof_of_existence::Pallet<Runtime>,
+    pub staking: staking::Pallet<Runtime>,
+    pub sudo: sudo::Pallet<Runtime>,
+}
+
+/// The runtime state as it is stored: the spec version of the runtime that wrote it, and the
+/// storage of every pallet at the storage version of that runtime.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct State {
+    pub spec_version: u32,
+    pub system: support::PalletStorage,
+    pub balance: support::PalletStorage,
+    pub proof_of_existence: support::PalletStorage,
+    pub staking: support::PalletStorage,
+    pub sudo: support::PalletStorage,
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Runtime {
+            system: system::Pallet::new(),
+            balance: balance::Pallet::new(),
+            proof_of_existence: proof_of_existence::Pallet::new(),
+            staking: staking::Pallet::new(),
+            sudo: sudo::Pallet::new(),
+        }
+    }
+
+    // The state to store, written by the current spec version.
+    pub fn state(&self) -> State {
+        State {
+            spec_version: VERSION.spec_version,
+            system: self.system.store(),
+            balance: self.balance.store(),
+            proof_of_existence: self.proof_of_existence.store(),
+            staking: self.staking.store(),
+            sudo: self.sudo.store(),
+        }
+    }
+
+    // Decodes `state`, running the storage migrations of every pallet when it was written by an
+    // older runtime. Returns the runtime along with the weight of the migrations.
+    pub fn on_runtime_upgrade(
+        state: State,
+    ) -> Result<(Runtime, support::Weight), support::DispatchError> {
+        if state.spec_version > VERSION.spec_version {
+            return Err(support::MigrationError::FutureSpecVersion {
+                found: state.spec_version,
+                supported: VERSION.spec_version,
+            }
+            .into());
+        }
+
+        let (system, system_weight) = Migrate::migrate(state.system)?;
+        let (balance, balance_weight) = Migrate::migrate(state.balance)?;
+        let (proof_of_existence, proof_of_existence_weight) =
+            Migrate::migrate(state.proof_of_existence)?;
+        let (staking, staking_weight) = Migrate::migrate(state.staking)?;
+        let (sudo, sudo_weight) = Migrate::migrate(state.sudo)?;
+        let runtime = Runtime {
+            system,
+            balance,
+            proof_of_existence,
+            staking,
+            sudo,
+        };
+
+        Ok((
+            runtime,
+            system_weight
+                + balance_weight
+                + proof_of_existence_weight
+                + staking_weight
+                + sudo_weight,
+        ))
+    }
+
+    // Runs the runtime upgrade against a snapshot of `state`, which is left untouched, so the
+    // migrations and the invariants they check can be tried before the upgraded state is stored.
+    pub fn try_runtime_upgrade(state: &State) -> Result<Runtime, support::DispatchError> {
+        let (runtime, _) = Self::on_runtime_upgrade(state.clone())?;
+        Ok(runtime)
+    }
+
+    pub fn execute_block(&mut self, block: types::Block) -> support::DispatchResult {
+        self.system.increment_block_number()?;
+
+        if self.system.block_number() != block.header.block_number {
+            return Err(support::DispatchError::BlockNumberMismatch);
+        }
+
+        let weight = self.on_initialize(block.header.block_number)?;
+        self.system.register_mandatory_weight(weight);
+
+        for (i, support::Extrinsic { caller, call }) in block.extrinsics.into_iter().enumerate() {
+            let result = match self
+                .system
+                .try_register_weight(<Self as system::Config>::EXTRINSIC_BASE_WEIGHT)
+            {
+                Ok(()) => {
+                    self.system.increment_nonce(&caller)?;
+                    self.dispatch(&caller, call)
+                }
+                Err(e) => Err(e.into()),
+            };
+            let index = i as u32;
+            let event = match result {
+                Ok(()) => system::Event::ExtrinsicSuccess { index },
+                Err(e) => {
+                    #[cfg(feature = "std")]
+                    std::eprintln!(
+                        "Extrinsic Error\n\tBlock Number: {}\n\tExtrinsic Number: {}\n\tError: {}",
+                        block.header.block_number,
+                        i,
+                        e
+                    );
+                    system::Event::ExtrinsicFailed {
+                        index,
+                        error: e.to_string(),
+                    }
+                }
+            };
+            self.system.deposit_event(event);
+        }
+
+        let weight = self.on_finalize(block.header.block_number)?;
+        self.system.register_mandatory_weight(weight);
+
+        Ok(())
+    }
+}
+
+// The runtime runs the hooks of every pallet in declaration order, adding up their weight.
+impl support::Hooks<types::BlockNumber> for Runtime {
+    fn on_initialize(
+        &mut self,
+        block_number: types::BlockNumber,
+    ) -> Result<support::Weight, support::DispatchError> {
+        Ok(self.system.on_initialize(block_number)?
+            + self.balance.on_initialize(block_number)?
+            + self.proof_of_existence.on_initialize(block_number)?
+            + (&mut self.staking, &mut self.balance).on_initialize(block_number)?
+            + self.sudo.on_initialize(block_number)?)
+    }
+
+    fn on_finalize(
+        &mut self,
+        block_number: types::BlockNumber,
+    ) -> Result<support::Weight, support::DispatchError> {
+        Ok(self.system.on_finalize(block_number)?
+            + self.balance.on_finalize(block_number)?
+            + self.proof_of_existence.on_finalize(block_number)?
+            + (&mut self.staking, &mut self.balance).on_finalize(block_number)?
+            + self.sudo.on_finalize(block_number)?)
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> crate::support::Dispatch<'a> for Runtime {
+    type Caller = &'a <Runtime as system::Config>::AccountId;
+    type Call = RuntimeCall;
+    // Dispatch a call on behalf of a caller. Increments the caller's nonce.
+    //
+    // Dispatch allows us to identify which underlying module call we want to execute.
+    // Note that we extract the `caller` from the extrinsic, and use that information
+    // to determine who we are executing the call on behalf of.
+    fn dispatch(
+        &mut self,
+        caller: Self::Caller,
+        runtime_call: Self::Call,
+    ) -> support::DispatchResult {
+        match runtime_call {
+            RuntimeCall::Balances(call) => self.balance.dispatch(caller, call)?,
+            RuntimeCall::ProofOfExistence(call) => {
+                self.proof_of_existence.dispatch(caller, call)?
+            }
+            RuntimeCall::Staking(call) => {
+                (&mut self.staking, &mut self.balance, &self.system).dispatch(caller, call)?
+            }
+            RuntimeCall::Sudo(call) => (&mut self.sudo, &mut self.system).dispatch(caller, call)?,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn extrinsics_are_limited_by_block_weight() {
+        let mut runtime = Runtime::new();
+        let alice = String::from("Alice");
+        let bob = String::from("Bob");
+        runtime.balance.set_balance(&alice, 200).unwrap();
+
+        let transfer = || support::Extrinsic {
+            caller: alice.clone(),
+            call: RuntimeCall::Balances(balance::Call::Transfer {
+                to: bob.clone(),
+                amount: 1,
+            }),
+        };
+        let block = types::Block {
+            header: support::Header { block_number: 1 },
+            extrinsics: (0..120).map(|_| transfer()).collect(),
+        };
+
+        runtime.execute_block(block).unwrap();
+
+        // The staking hooks use 1 weight each, leaving room for 99 extrinsics of 10.
+        assert_eq!(runtime.balance.balance(&bob), 99);
+        assert_eq!(runtime.system.get_nonce(&alice), 99);
+        assert_eq!(runtime.system.block_weight(), 992);
+        assert_eq!(
+            runtime.system.events()[99],
+            system::Event::ExtrinsicFailed {
+                index: 99,
+                error: String::from("Block weight limit exhausted"),
+            }
+        );
+    }
+
+    #[test]
+    fn runtime_upgrade_runs_pending_migrations() {
+        let mut runtime = Runtime::new();
+        let alice = String::from("Alice");
+        runtime.balance.set_balance(&alice, 100).unwrap();
+
+        // The balance storage as version 0 wrote it, without the free issuance.
+        let mut state = runtime.state();
+        state.spec_version = 1;
+        state.balance = support::PalletStorage {
+            version: 0,
+            data: BTreeMap::from([(alice.clone(), 100u128)]).encode(),
+        };
+
+        let upgraded = Runtime::try_runtime_upgrade(&state).unwrap();
+        assert_eq!(upgraded.balance.balance(&alice), 100);
+        assert_eq!(upgraded.balance.free_issuance(), 100);
+        assert_eq!(upgraded.state().spec_version, VERSION.spec_version);
+        assert_eq!(upgraded.state().balance.version, 1);
+
+        let (_, weight) = Runtime::on_runtime_upgrade(state).unwrap();
+        assert_eq!(weight, 1);
+        let (_, weight) = Runtime::on_runtime_upgrade(upgraded.state()).unwrap();
+        assert_eq!(weight, 0);
+    }
+
+    #[test]
+    fn sudo_schedules_new_runtime_code() {
+        let mut runtime = Runtime::new();
+        let alice = String::from("Alice");
+        runtime.sudo.set_key(Some(alice.clone()));
+
+        let block = types::Block {
+            header: support::Header { block_number: 1 },
+            extrinsics: vec![support::Extrinsic {
+                caller: alice,
+                call: RuntimeCall::Sudo(sudo::Call::SetCode {
+                    code: b"new code".to_vec(),
+                }),
+            }],
+        };
+        runtime.execute_block(block).unwrap();
+
+        assert_eq!(
+            runtime.system.take_pending_code(),
+            Some(b"new code".to_vec())
+        );
+    }
+
+    #[test]
+    fn runtime_upgrade_rejects_newer_state() {
+        let mut state = Runtime::new().state();
+        state.spec_version = VERSION.spec_version + 1;
+
+        let err = Runtime::try_runtime_upgrade(&state).unwrap_err();
+        assert!(matches!(
+            err,
+            support::DispatchError::MigrationError(
+                support::MigrationError::FutureSpecVersion { .. }
+            )
+        ));
+    }
+}
@@ -1,153 +1,153 @@
-mod balance;
-mod proof_of_existence;
-mod support;
-mod system;
-mod types {
-    use crate::support;
-
-    pub type AccountId = String;
-    pub type Balance = u128;
-    pub type BlockNumber = u128;
-    pub type Nonce = u32;
-    pub type Extrinsic<'a> = support::Extrinsic<&'a AccountId, crate::RuntimeCall<'a>>;
-    pub type Header = support::Header<BlockNumber>;
-    pub type Block<'a> = support::Block<Header, Extrinsic<'a>>;
-    pub type Content = String;
-}
-
 use std::error::Error;
 
-use support::Dispatch;
-
-pub enum RuntimeCall<'a> {
-    Balances(balance::Call<'a, Runtime>),
-    ProofOfExistence(proof_of_existence::Call<'a, Runtime>),
-}
-
-impl system::Config for Runtime {
-    type AccountId = types::AccountId;
-    type Nonce = types::Nonce;
-    type BlockNumber = types::BlockNumber;
-}
-
-impl balance::Config for Runtime {
-    type Balance = types::Balance;
-}
-
-impl proof_of_existence::Config for Runtime {
-    type Content = types::Content;
-}
-
-#[derive(Debug)]
-pub struct Runtime {
-    system: system::Pallet<Runtime>,
-    balance: balance::Pallet<Runtime>,
-    proof_of_existence: proof_of_existence::Pallet<Runtime>,
-}
-
-impl Runtime {
-    pub fn new() -> Self {
-        Runtime {
-            system: system::Pallet::new(),
-            balance: balance::Pallet::new(),
-            proof_of_existence: proof_of_existence::Pallet::new(),
-        }
-    }
-
-    fn execute_block(&mut self, block: types::Block) -> support::DispatchResult {
-        self.system.increment_block_number()?;
-
-        if self.system.block_number() != block.header.block_number {
-            return Err(support::DispatchError::BlockNumberMismatch);
-        }
-
-        for (i, support::Extrinsic { caller, call }) in block.extrinsics.into_iter().enumerate() {
-            self.system.increment_nonce(&caller)?;
-            let _ = self.dispatch(&caller, call).map_err(|e| {
-                eprintln!(
-                    "Extrinsic Error\n\tBlock Number: {}\n\tExtrinsic Number: {}\n\tError: {}",
-                    block.header.block_number, i, e
-                )
-            });
-        }
-
-        Ok(())
-    }
-}
-
-impl<'a> crate::support::Dispatch<'a> for Runtime {
-    type Caller = &'a <Runtime as system::Config>::AccountId;
-    type Call = RuntimeCall<'a>;
-    // Dispatch a call on behalf of a caller. Increments the caller's nonce.
-    //
-    // Dispatch allows us to identify which underlying module call we want to execute.
-    // Note that we extract the `caller` from the extrinsic, and use that information
-    // to determine who we are executing the call on behalf of.
-    fn dispatch(
-        &mut self,
-        caller: Self::Caller,
-        runtime_call: Self::Call,
-    ) -> support::DispatchResult {
-        match runtime_call {
-            RuntimeCall::Balances(call) => self.balance.dispatch(caller, call)?,
-            RuntimeCall::ProofOfExistence(call) => {
-                self.proof_of_existence.dispatch(caller, call)?
-            }
-        };
-        Ok(())
-    }
-}
+use rsm::db::ChainDb;
+use rsm::{balance, proof_of_existence, staking, support, types, Runtime, RuntimeCall};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut runtime = Runtime::new();
-
     let alice = String::from("Alice");
     let bob = String::from("Bob");
     let charlie = String::from("Charlie");
 
-    runtime.balance.set_balance(&alice, 100);
+    // With `--db PATH`, the chain is persisted at PATH and the node resumes from its latest state.
+    let db = arg_value("--db").map(ChainDb::open).transpose()?;
+    let runtime = match db.as_ref().map(ChainDb::state).transpose()?.flatten() {
+        Some(state) => state,
+        None => {
+            let mut genesis = Runtime::new();
+            genesis.balance.set_balance(&alice, 100)?;
+            genesis.sudo.set_key(Some(alice.clone()));
+            genesis
+        }
+    };
+    let head = runtime.system.block_number();
 
     let block_1 = types::Block {
-        header: support::Header { block_number: 1 },
+        header: support::Header {
+            block_number: head + 1,
+        },
         extrinsics: vec![
             support::Extrinsic {
-                caller: &alice,
+                caller: alice.clone(),
                 call: RuntimeCall::Balances(balance::Call::Transfer {
-                    to: &bob,
+                    to: bob.clone(),
                     amount: 50,
                 }),
             },
             support::Extrinsic {
-                caller: &alice,
+                caller: alice.clone(),
                 call: RuntimeCall::Balances(balance::Call::Transfer {
-                    to: &charlie,
+                    to: charlie.clone(),
                     amount: 40,
                 }),
             },
         ],
     };
 
-    runtime
-        .execute_block(block_1)
-        .map_err(|e| eprintln!("Error when executing block: {e}"))
-        .expect("[ERROR] Failed to proccess block!");
-
     let block_2 = types::Block {
-        header: support::Header { block_number: 2 },
+        header: support::Header {
+            block_number: head + 2,
+        },
         extrinsics: vec![support::Extrinsic {
-            caller: &alice,
+            caller: alice.clone(),
             call: RuntimeCall::ProofOfExistence(proof_of_existence::Call::CreateClaim {
                 claim: String::from("Asset"),
             }),
         }],
     };
 
-    runtime
-        .execute_block(block_2)
-        .map_err(|e| eprintln!("Error when executing block: {e}"))
-        .expect("[ERROR] Failed to proccess block!");
+    let block_3 = types::Block {
+        header: support::Header {
+            block_number: head + 3,
+        },
+        extrinsics: vec![
+            support::Extrinsic {
+                caller: bob.clone(),
+                call: RuntimeCall::Staking(staking::Call::Bond { amount: 30 }),
+            },
+            support::Extrinsic {
+                caller: bob.clone(),
+                call: RuntimeCall::Staking(staking::Call::Validate),
+            },
+            support::Extrinsic {
+                caller: charlie.clone(),
+                call: RuntimeCall::Staking(staking::Call::Bond { amount: 20 }),
+            },
+            support::Extrinsic {
+                caller: charlie.clone(),
+                call: RuntimeCall::Staking(staking::Call::Nominate {
+                    targets: vec![bob.clone()],
+                }),
+            },
+        ],
+    };
+
+    let runtime = execute_blocks(runtime, vec![block_1, block_2, block_3], db.as_ref())?;
 
     println!("{:?}", runtime);
 
+    for who in [&alice, &bob, &charlie] {
+        println!(
+            "{who}: free {}, bonded {}, unlocking {:?}, nonce {}",
+            runtime.balance.balance(who),
+            runtime.staking.bonded(who),
+            runtime.staking.unlocking(who),
+            runtime.system.get_nonce(who),
+        );
+    }
+    println!(
+        "Block {}: weight {}, era {}",
+        runtime.system.block_number(),
+        runtime.system.block_weight(),
+        runtime.staking.current_era()
+    );
+    for validator in runtime.staking.elected() {
+        println!(
+            "Validator {validator}: {:?}",
+            runtime.staking.exposure(&validator)
+        );
+    }
+
     Ok(())
 }
+
+// Executes the blocks with the native runtime or, when a `--wasm PATH` argument is given, with the
+// WebAssembly build of the runtime at PATH. Each block is imported into `db` once executed.
+fn execute_blocks(
+    mut runtime: Runtime,
+    blocks: Vec<types::Block>,
+    db: Option<&ChainDb>,
+) -> Result<Runtime, Box<dyn Error>> {
+    #[cfg(feature = "executor")]
+    if let Some(path) = arg_value("--wasm") {
+        let mut executor = rsm::executor::Executor::with_genesis(std::fs::read(path)?, &runtime);
+        for block in blocks {
+            executor
+                .execute_block(&block)
+                .map_err(|e| eprintln!("Error when executing block: {e}"))
+                .expect("[ERROR] Failed to proccess block!");
+            runtime = executor
+                .state()?
+                .ok_or("The runtime did not write its state")?;
+            if let Some(db) = db {
+                db.import(block, &runtime)?;
+            }
+        }
+        return Ok(runtime);
+    }
+
+    for block in blocks {
+        runtime
+            .execute_block(block.clone())
+            .map_err(|e| eprintln!("Error when executing block: {e}"))
+            .expect("[ERROR] Failed to proccess block!");
+        if let Some(db) = db {
+            db.import(block, &runtime)?;
+        }
+    }
+
+    Ok(runtime)
+}
+
+fn arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
@@ -1,8 +1,11 @@
+use alloc::collections::BTreeMap;
 use core::fmt::Debug;
-use std::collections::BTreeMap;
 
+use codec::{Decode, Encode};
 use thiserror::Error;
 
+use crate::support::{Migrate, StorageVersion};
+
 #[derive(Debug, Error)]
 pub enum ClaimError {
     #[error("Claim already exists.")]
@@ -17,12 +20,12 @@ pub trait Config: crate::system::Config {
     /// O tipo que representa o conteúdo que pode ser reivindicado usando este pallet.
     /// Pode ser o conteúdo diretamente como bytes, ou melhor ainda, o hash desse conteúdo.
     /// Deixamos essa decisão para o desenvolvedor do runtime.
-    type Content: Debug + Ord;
+    type Content: Debug + Ord + Encode + Decode;
 }
 
 /// Este é o Módulo de Prova de Existência.
 /// É um módulo simples que permite que contas reivindiquem a existência de alguns dados.
-#[derive(Debug)]
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Pallet<T: Config> {
     /// Um simples mapa de armazenamento de conteúdo para o proprietário desse conteúdo.
     /// As contas podem fazer várias reivindicações diferentes, mas cada reivindicação só pode ter um proprietário.
@@ -42,6 +45,11 @@ impl<T: Config> Pallet<T> {
         self.claims.get(claim).cloned()
     }
 
+    /// Itera sobre todas as reivindicações e seus proprietários.
+    pub fn claims(&self) -> impl Iterator<Item = (&T::Content, &T::AccountId)> {
+        self.claims.iter()
+    }
+
     /// Cria uma nova reivindicação em nome do `caller`.
     /// Esta função retornará um erro se alguém já tiver reivindicado esse conteúdo.
     pub fn create_claim(
@@ -79,18 +87,18 @@ impl<T: Config> Pallet<T> {
     }
 }
 
-pub enum Call<'a, T: Config> {
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum Call<T: Config> {
     CreateClaim { claim: T::Content },
-    RevokeClaim { claim: &'a T::Content },
+    RevokeClaim { claim: T::Content },
 }
 
 impl<'a, T: Config> crate::support::Dispatch<'a> for Pallet<T>
 where
     T::AccountId: 'a,
-    T::Content: 'a,
 {
     type Caller = &'a T::AccountId;
-    type Call = Call<'a, T>;
+    type Call = Call<T>;
 
     fn dispatch(
         &mut self,
@@ -99,12 +107,24 @@ where
     ) -> crate::support::DispatchResult {
         match call {
             Call::CreateClaim { claim } => self.create_claim(caller.clone(), claim)?,
-            Call::RevokeClaim { claim } => self.revoke_claim(caller, claim)?,
+            Call::RevokeClaim { claim } => self.revoke_claim(caller, &claim)?,
         }
         Ok(())
     }
 }
 
+impl<T: Config> Default for Pallet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Config> crate::support::Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> Migrate for Pallet<T> {
+    const STORAGE_VERSION: StorageVersion = 0;
+}
+
 #[cfg(test)]
 mod test {
     struct TestConfig;
@@ -144,7 +164,7 @@ mod test {
         let err = poe
             .create_claim(String::from("bob"), asset.clone())
             .unwrap_err();
-        assert_eq!(matches!(err, super::ClaimError::ClaimAlreadyExists), true);
+        assert!(matches!(err, super::ClaimError::ClaimAlreadyExists));
     }
 
     #[test]
@@ -152,14 +172,11 @@ mod test {
         let (alice, asset, mut poe) = setup();
 
         let err = poe.revoke_claim(&alice, &asset).unwrap_err();
-        assert_eq!(matches!(err, super::ClaimError::ClaimDoesNotExists), true);
+        assert!(matches!(err, super::ClaimError::ClaimDoesNotExists));
 
         poe.create_claim(alice.clone(), asset.clone()).unwrap();
         let err = poe.revoke_claim(&String::from("bob"), &asset).unwrap_err();
-        assert_eq!(
-            matches!(err, super::ClaimError::ClaimerNotOwnerContent),
-            true
-        );
+        assert!(matches!(err, super::ClaimError::ClaimerNotOwnerContent));
 
         assert_eq!(poe.revoke_claim(&alice, &asset).unwrap(), ());
         assert_eq!(poe.get_claim(&asset), None);
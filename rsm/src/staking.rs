@@ -0,0 +1,788 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use codec::{Decode, DecodeAll, Encode};
+use num::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, One, Zero};
+use thiserror::Error;
+
+use crate::balance::{self, TransferError};
+use crate::support::{DispatchError, Hooks, Migrate, MigrationError, StorageVersion, Weight};
+use crate::system;
+
+/// Weight of checking whether an era boundary was reached.
+const ERA_CHECK_WEIGHT: Weight = 1;
+/// Weight of processing a single staker during an election or an era payout.
+const STAKER_WEIGHT: Weight = 10;
+
+#[derive(Debug, Error)]
+pub enum StakingError {
+    #[error("Account has no bonded funds")]
+    NotBonded,
+    #[error("Insufficient bonded balance")]
+    InsufficientBond,
+    #[error("Nomination list is empty")]
+    EmptyNominations,
+    #[error("Nomination target is not a validator candidate")]
+    InvalidNominationTarget,
+    #[error("Too many unlocking chunks, withdraw the unlocked funds first")]
+    NoMoreChunks,
+    #[error("Overflow when computing staking values")]
+    Overflow,
+    #[error(transparent)]
+    TransferError(#[from] TransferError),
+}
+
+pub trait Config:
+    system::Config<BlockNumber: Ord> + balance::Config<Balance: CheckedMul + CheckedDiv + Ord>
+{
+    /// Number of blocks in an era. Validators are elected at the first block of an era and
+    /// rewarded at its last block.
+    const ERA_LENGTH: Self::BlockNumber;
+    /// Number of blocks unbonded funds stay locked before they can be withdrawn.
+    const BONDING_DURATION: Self::BlockNumber;
+    /// Maximum number of unbonded chunks an account can wait for at once. Unbonding again in the
+    /// same block adds to the last chunk instead.
+    const MAX_UNLOCKING_CHUNKS: usize;
+    /// Maximum number of validators elected at each era boundary.
+    const VALIDATOR_COUNT: usize;
+    /// Amount minted at the end of each era and shared among the elected validators and their
+    /// nominators, proportionally to their stake.
+    const ERA_REWARD: Self::Balance;
+}
+
+/// The stake backing an elected validator for the duration of an era.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct Exposure<AccountId, Balance> {
+    /// The validator's own bonded stake.
+    pub own: Balance,
+    /// The nominators assigned to this validator and the stake they put behind it.
+    pub others: Vec<(AccountId, Balance)>,
+    /// The sum of `own` and every stake in `others`.
+    pub total: Balance,
+}
+
+/// Unbonded funds together with the block number they unlock at.
+pub type UnlockChunk<T> = (
+    <T as balance::Config>::Balance,
+    <T as system::Config>::BlockNumber,
+);
+
+/// This is the Staking Module.
+/// Accounts bond funds from the balance pallet and either offer themselves as validators or
+/// nominate validators they trust. At each era boundary the best backed validators are elected
+/// and, at the end of the era, newly minted rewards are shared among the elected stake.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct Pallet<T: Config> {
+    current_era: u32,
+    next_era_start: T::BlockNumber,
+    /// Funds currently bonded by each account.
+    ledger: BTreeMap<T::AccountId, T::Balance>,
+    /// Unbonded funds waiting for the bonding duration to pass, with the block they unlock at.
+    unlocking: BTreeMap<T::AccountId, Vec<UnlockChunk<T>>>,
+    /// Accounts willing to be elected as validators.
+    validators: BTreeSet<T::AccountId>,
+    /// Nominators and their validator targets, in order of preference.
+    nominators: BTreeMap<T::AccountId, Vec<T::AccountId>>,
+    /// The validators elected for the current era.
+    exposures: BTreeMap<T::AccountId, Exposure<T::AccountId, T::Balance>>,
+}
+
+impl<T: Config> Pallet<T> {
+    pub fn new() -> Self {
+        Self {
+            current_era: 0,
+            next_era_start: T::BlockNumber::zero(),
+            ledger: BTreeMap::new(),
+            unlocking: BTreeMap::new(),
+            validators: BTreeSet::new(),
+            nominators: BTreeMap::new(),
+            exposures: BTreeMap::new(),
+        }
+    }
+
+    pub fn current_era(&self) -> u32 {
+        self.current_era
+    }
+
+    pub fn bonded(&self, who: &T::AccountId) -> T::Balance {
+        *self.ledger.get(who).unwrap_or(&T::Balance::zero())
+    }
+
+    pub fn unlocking(&self, who: &T::AccountId) -> &[UnlockChunk<T>] {
+        self.unlocking
+            .get(who)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn elected(&self) -> Vec<T::AccountId> {
+        self.exposures.keys().cloned().collect()
+    }
+
+    pub fn exposure(
+        &self,
+        validator: &T::AccountId,
+    ) -> Option<&Exposure<T::AccountId, T::Balance>> {
+        self.exposures.get(validator)
+    }
+
+    /// Moves `amount` from the free balance of `who` into its staking ledger.
+    pub fn bond(
+        &mut self,
+        balance: &mut balance::Pallet<T>,
+        who: &T::AccountId,
+        amount: T::Balance,
+    ) -> Result<(), StakingError> {
+        let new_bonded = self
+            .bonded(who)
+            .checked_add(&amount)
+            .ok_or(StakingError::Overflow)?;
+
+        balance.withdraw(who, amount)?;
+        self.ledger.insert(who.clone(), new_bonded);
+
+        Ok(())
+    }
+
+    /// Schedules `amount` of the bonded funds of `who` to be unlocked after the bonding duration,
+    /// counted from block `now`. An account left with nothing bonded stops validating or
+    /// nominating.
+    pub fn unbond(
+        &mut self,
+        who: &T::AccountId,
+        amount: T::Balance,
+        now: T::BlockNumber,
+    ) -> Result<(), StakingError> {
+        let new_bonded = self
+            .ledger
+            .get(who)
+            .ok_or(StakingError::NotBonded)?
+            .checked_sub(&amount)
+            .ok_or(StakingError::InsufficientBond)?;
+        let unlock_at = now
+            .checked_add(&T::BONDING_DURATION)
+            .ok_or(StakingError::Overflow)?;
+
+        let chunks = self.unlocking.entry(who.clone()).or_default();
+        match chunks.last_mut() {
+            Some((value, at)) if *at == unlock_at => {
+                *value = value.checked_add(&amount).ok_or(StakingError::Overflow)?;
+            }
+            _ => {
+                if chunks.len() >= T::MAX_UNLOCKING_CHUNKS {
+                    return Err(StakingError::NoMoreChunks);
+                }
+                chunks.push((amount, unlock_at));
+            }
+        }
+
+        if new_bonded.is_zero() {
+            self.ledger.remove(who);
+            self.chill(who);
+        } else {
+            self.ledger.insert(who.clone(), new_bonded);
+        }
+
+        Ok(())
+    }
+
+    /// Returns every chunk of `who` unlocked by block `now` to its free balance.
+    pub fn withdraw_unbonded(
+        &mut self,
+        balance: &mut balance::Pallet<T>,
+        who: &T::AccountId,
+        now: T::BlockNumber,
+    ) -> Result<(), StakingError> {
+        let Some(chunks) = self.unlocking.get_mut(who) else {
+            return Ok(());
+        };
+
+        let amount = chunks
+            .iter()
+            .filter(|(_, unlock_at)| *unlock_at <= now)
+            .try_fold(T::Balance::zero(), |acc, (value, _)| acc.checked_add(value))
+            .ok_or(StakingError::Overflow)?;
+        balance.deposit(who, amount)?;
+
+        chunks.retain(|(_, unlock_at)| *unlock_at > now);
+        if chunks.is_empty() {
+            self.unlocking.remove(who);
+        }
+
+        Ok(())
+    }
+
+    /// Declares `who` as a validator candidate for the next elections.
+    pub fn validate(&mut self, who: &T::AccountId) -> Result<(), StakingError> {
+        if !self.ledger.contains_key(who) {
+            return Err(StakingError::NotBonded);
+        }
+
+        self.nominators.remove(who);
+        self.validators.insert(who.clone());
+
+        Ok(())
+    }
+
+    /// Backs the given validator candidates with the bonded funds of `who`.
+    pub fn nominate(
+        &mut self,
+        who: &T::AccountId,
+        targets: Vec<T::AccountId>,
+    ) -> Result<(), StakingError> {
+        if !self.ledger.contains_key(who) {
+            return Err(StakingError::NotBonded);
+        }
+        if targets.is_empty() {
+            return Err(StakingError::EmptyNominations);
+        }
+        if targets
+            .iter()
+            .any(|target| !self.validators.contains(target))
+        {
+            return Err(StakingError::InvalidNominationTarget);
+        }
+
+        self.validators.remove(who);
+        self.nominators.insert(who.clone(), targets);
+
+        Ok(())
+    }
+
+    /// Stops `who` from validating or nominating. Its funds stay bonded.
+    pub fn chill(&mut self, who: &T::AccountId) {
+        self.validators.remove(who);
+        self.nominators.remove(who);
+    }
+
+    /// Elects the `VALIDATOR_COUNT` candidates with the most approval stake. Each nominator's stake
+    /// is then assigned to its most preferred target that got elected.
+    fn elect(&mut self) -> Result<(), StakingError> {
+        let mut scores: BTreeMap<&T::AccountId, T::Balance> = self
+            .validators
+            .iter()
+            .filter_map(|validator| Some((validator, *self.ledger.get(validator)?)))
+            .collect();
+
+        for (nominator, targets) in &self.nominators {
+            let Some(stake) = self.ledger.get(nominator) else {
+                continue;
+            };
+            for target in targets {
+                if let Some(score) = scores.get_mut(target) {
+                    *score = score.checked_add(stake).ok_or(StakingError::Overflow)?;
+                }
+            }
+        }
+
+        let mut ranking: Vec<_> = scores.into_iter().collect();
+        ranking.sort_by(|(a, a_score), (b, b_score)| b_score.cmp(a_score).then(a.cmp(b)));
+        ranking.truncate(T::VALIDATOR_COUNT);
+
+        let mut exposures: BTreeMap<_, _> = ranking
+            .into_iter()
+            .map(|(validator, _)| {
+                let own = self.bonded(validator);
+                let exposure = Exposure {
+                    own,
+                    others: Vec::new(),
+                    total: own,
+                };
+                (validator.clone(), exposure)
+            })
+            .collect();
+
+        for (nominator, targets) in &self.nominators {
+            let Some(stake) = self.ledger.get(nominator) else {
+                continue;
+            };
+            let assigned = targets
+                .iter()
+                .find(|target| exposures.contains_key(*target));
+            if let Some(exposure) = assigned.and_then(|target| exposures.get_mut(target)) {
+                exposure.total = exposure
+                    .total
+                    .checked_add(stake)
+                    .ok_or(StakingError::Overflow)?;
+                exposure.others.push((nominator.clone(), *stake));
+            }
+        }
+
+        self.exposures = exposures;
+
+        Ok(())
+    }
+
+    /// Mints `ERA_REWARD` into the free balance of everyone exposed in the current era,
+    /// proportionally to their stake. Returns the number of stakers rewarded.
+    fn reward_era(&mut self, balance: &mut balance::Pallet<T>) -> Result<usize, StakingError> {
+        let total_stake = self
+            .exposures
+            .values()
+            .try_fold(T::Balance::zero(), |acc, exposure| {
+                acc.checked_add(&exposure.total)
+            })
+            .ok_or(StakingError::Overflow)?;
+
+        if total_stake.is_zero() {
+            return Ok(0);
+        }
+
+        // Every reward is computed and checked before any is paid, so that an overflow pays none.
+        let mut rewards: BTreeMap<&T::AccountId, T::Balance> = BTreeMap::new();
+        let mut rewarded = 0;
+        for (validator, exposure) in &self.exposures {
+            let stakers = core::iter::once((validator, &exposure.own))
+                .chain(exposure.others.iter().map(|(who, stake)| (who, stake)));
+
+            for (who, stake) in stakers {
+                let reward = T::ERA_REWARD
+                    .checked_mul(stake)
+                    .and_then(|value| value.checked_div(&total_stake))
+                    .ok_or(StakingError::Overflow)?;
+                let total = rewards.entry(who).or_insert_with(T::Balance::zero);
+                *total = total.checked_add(&reward).ok_or(StakingError::Overflow)?;
+                rewarded += 1;
+            }
+        }
+
+        // Every balance is part of the free issuance, so the deposits fit when their sum does.
+        rewards
+            .values()
+            .try_fold(balance.free_issuance(), |total, reward| {
+                total.checked_add(reward)
+            })
+            .ok_or(TransferError::OverflowIssuance)?;
+        for (who, reward) in rewards {
+            balance.deposit(who, reward)?;
+        }
+
+        Ok(rewarded)
+    }
+}
+
+impl<T: Config> Default for Pallet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Like dispatch, the staking hooks need the balance pallet to mint era rewards.
+impl<T: Config> Hooks<T::BlockNumber> for (&mut Pallet<T>, &mut balance::Pallet<T>) {
+    /// Starts a new era when its first block is reached, electing the validators for it.
+    fn on_initialize(&mut self, block_number: T::BlockNumber) -> Result<Weight, DispatchError> {
+        let (staking, _) = self;
+
+        if block_number < staking.next_era_start {
+            return Ok(ERA_CHECK_WEIGHT);
+        }
+
+        staking.next_era_start = block_number
+            .checked_add(&T::ERA_LENGTH)
+            .ok_or(StakingError::Overflow)?;
+        staking.current_era += 1;
+        staking.elect()?;
+
+        let stakers = staking.validators.len() + staking.nominators.len();
+        Ok(ERA_CHECK_WEIGHT + stakers as Weight * STAKER_WEIGHT)
+    }
+
+    /// Pays out the era reward when the last block of the era is finalized.
+    fn on_finalize(&mut self, block_number: T::BlockNumber) -> Result<Weight, DispatchError> {
+        let (staking, balance) = self;
+        let next_block = block_number
+            .checked_add(&T::BlockNumber::one())
+            .ok_or(StakingError::Overflow)?;
+
+        if next_block != staking.next_era_start {
+            return Ok(ERA_CHECK_WEIGHT);
+        }
+
+        let rewarded = staking.reward_era(balance)?;
+        Ok(ERA_CHECK_WEIGHT + rewarded as Weight * STAKER_WEIGHT)
+    }
+}
+
+impl<T: Config> Migrate for Pallet<T> {
+    const STORAGE_VERSION: StorageVersion = 1;
+
+    fn migrate_from(
+        from: StorageVersion,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, Weight), MigrationError> {
+        match from {
+            // Version 1 reads the block number from the system pallet instead of keeping a copy.
+            0 => {
+                let v0::Pallet {
+                    block_number: _,
+                    current_era,
+                    next_era_start,
+                    ledger,
+                    unlocking,
+                    validators,
+                    nominators,
+                    exposures,
+                } = v0::Pallet::<T>::decode_all(&mut &data[..])?;
+                let pallet = Pallet::<T> {
+                    current_era,
+                    next_era_start,
+                    ledger,
+                    unlocking,
+                    validators,
+                    nominators,
+                    exposures,
+                };
+                Ok((pallet.encode(), pallet.ledger.len() as Weight))
+            }
+            _ => Err(MigrationError::MissingMigration(from)),
+        }
+    }
+}
+
+/// The storage layouts of older storage versions, decoded by the migrations.
+pub mod v0 {
+    use super::*;
+
+    #[derive(Decode)]
+    pub struct Pallet<T: Config> {
+        pub block_number: T::BlockNumber,
+        pub current_era: u32,
+        pub next_era_start: T::BlockNumber,
+        pub ledger: BTreeMap<T::AccountId, T::Balance>,
+        pub unlocking: BTreeMap<T::AccountId, Vec<UnlockChunk<T>>>,
+        pub validators: BTreeSet<T::AccountId>,
+        pub nominators: BTreeMap<T::AccountId, Vec<T::AccountId>>,
+        pub exposures: BTreeMap<T::AccountId, Exposure<T::AccountId, T::Balance>>,
+    }
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum Call<T: Config> {
+    Bond { amount: T::Balance },
+    Unbond { amount: T::Balance },
+    WithdrawUnbonded,
+    Validate,
+    Nominate { targets: Vec<T::AccountId> },
+    Chill,
+}
+
+/// Staking calls move funds in and out of the balance pallet, so they are dispatched on both
+/// pallets at once. Unbonding also reads the current block number from the system pallet.
+impl<'a, T: Config> crate::support::Dispatch<'a>
+    for (&mut Pallet<T>, &mut balance::Pallet<T>, &system::Pallet<T>)
+where
+    T::AccountId: 'a,
+{
+    type Caller = &'a T::AccountId;
+    type Call = Call<T>;
+
+    fn dispatch(
+        &mut self,
+        caller: Self::Caller,
+        call: Self::Call,
+    ) -> crate::support::DispatchResult {
+        let (staking, balance, system) = self;
+        let now = system.block_number();
+        match call {
+            Call::Bond { amount } => staking.bond(balance, caller, amount)?,
+            Call::Unbond { amount } => staking.unbond(caller, amount, now)?,
+            Call::WithdrawUnbonded => staking.withdraw_unbonded(balance, caller, now)?,
+            Call::Validate => staking.validate(caller)?,
+            Call::Nominate { targets } => staking.nominate(caller, targets)?,
+            Call::Chill => staking.chill(caller),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::support::{Dispatch, PalletStorage};
+
+    struct TestConfig;
+
+    impl system::Config for TestConfig {
+        type AccountId = String;
+        type Nonce = u32;
+        type BlockNumber = u32;
+    }
+
+    impl balance::Config for TestConfig {
+        type Balance = u128;
+    }
+
+    impl Config for TestConfig {
+        const ERA_LENGTH: u32 = 5;
+        const BONDING_DURATION: u32 = 3;
+        const MAX_UNLOCKING_CHUNKS: usize = 2;
+        const VALIDATOR_COUNT: usize = 2;
+        const ERA_REWARD: u128 = 1_000;
+    }
+
+    fn setup() -> (Pallet<TestConfig>, balance::Pallet<TestConfig>) {
+        let mut balance = balance::Pallet::new();
+        for who in ["Alice", "Bob", "Charlie", "Dave"] {
+            balance.set_balance(&String::from(who), 1_000).unwrap();
+        }
+        (Pallet::new(), balance)
+    }
+
+    fn run_to_block(
+        staking: &mut Pallet<TestConfig>,
+        balance: &mut balance::Pallet<TestConfig>,
+        from: u32,
+        to: u32,
+    ) {
+        let mut pallets = (staking, balance);
+        for block_number in from..=to {
+            pallets.on_initialize(block_number).unwrap();
+            pallets.on_finalize(block_number).unwrap();
+        }
+    }
+
+    #[test]
+    fn bond_moves_free_balance() {
+        let (mut staking, mut balance) = setup();
+        let alice = String::from("Alice");
+
+        staking.bond(&mut balance, &alice, 400).unwrap();
+        assert_eq!(staking.bonded(&alice), 400);
+        assert_eq!(balance.balance(&alice), 600);
+
+        let err = staking.bond(&mut balance, &alice, 601).unwrap_err();
+        assert!(matches!(
+            err,
+            StakingError::TransferError(TransferError::InsufficientBalance)
+        ));
+        assert_eq!(staking.bonded(&alice), 400);
+    }
+
+    #[test]
+    fn unbond_waits_for_bonding_duration() {
+        let (mut staking, mut balance) = setup();
+        let alice = String::from("Alice");
+
+        staking.bond(&mut balance, &alice, 400).unwrap();
+        staking.unbond(&alice, 100, 1).unwrap();
+        assert_eq!(staking.bonded(&alice), 300);
+        assert_eq!(staking.unlocking(&alice), &[(100, 4)]);
+
+        staking.withdraw_unbonded(&mut balance, &alice, 3).unwrap();
+        assert_eq!(balance.balance(&alice), 600);
+
+        staking.withdraw_unbonded(&mut balance, &alice, 4).unwrap();
+        assert_eq!(balance.balance(&alice), 700);
+        assert!(staking.unlocking(&alice).is_empty());
+
+        let err = staking.unbond(&alice, 301, 4).unwrap_err();
+        assert!(matches!(err, StakingError::InsufficientBond));
+    }
+
+    #[test]
+    fn unlocking_chunks_are_limited() {
+        let (mut staking, mut balance) = setup();
+        let alice = String::from("Alice");
+
+        staking.bond(&mut balance, &alice, 400).unwrap();
+        staking.unbond(&alice, 100, 1).unwrap();
+        staking.unbond(&alice, 50, 1).unwrap();
+        staking.unbond(&alice, 100, 2).unwrap();
+        assert_eq!(staking.unlocking(&alice), &[(150, 4), (100, 5)]);
+
+        let err = staking.unbond(&alice, 100, 3).unwrap_err();
+        assert!(matches!(err, StakingError::NoMoreChunks));
+        assert_eq!(staking.bonded(&alice), 150);
+
+        staking.withdraw_unbonded(&mut balance, &alice, 4).unwrap();
+        staking.unbond(&alice, 100, 4).unwrap();
+        assert_eq!(staking.unlocking(&alice), &[(100, 5), (100, 7)]);
+    }
+
+    #[test]
+    fn failed_withdrawal_keeps_chunks() {
+        let (mut staking, mut balance) = (Pallet::<TestConfig>::new(), balance::Pallet::new());
+        let alice = String::from("Alice");
+        balance.set_balance(&alice, 400).unwrap();
+
+        staking.bond(&mut balance, &alice, 400).unwrap();
+        staking.unbond(&alice, 100, 1).unwrap();
+        balance.set_balance(&alice, u128::MAX - 50).unwrap();
+
+        let err = staking
+            .withdraw_unbonded(&mut balance, &alice, 4)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            StakingError::TransferError(TransferError::OverflowBalance)
+        ));
+        assert_eq!(staking.unlocking(&alice), &[(100, 4)]);
+        assert_eq!(balance.balance(&alice), u128::MAX - 50);
+    }
+
+    #[test]
+    fn nominate_requires_validator_targets() {
+        let (mut staking, mut balance) = setup();
+        let alice = String::from("Alice");
+        let bob = String::from("Bob");
+
+        let err = staking.nominate(&bob, vec![alice.clone()]).unwrap_err();
+        assert!(matches!(err, StakingError::NotBonded));
+
+        staking.bond(&mut balance, &bob, 100).unwrap();
+        let err = staking.nominate(&bob, vec![]).unwrap_err();
+        assert!(matches!(err, StakingError::EmptyNominations));
+        let err = staking.nominate(&bob, vec![alice.clone()]).unwrap_err();
+        assert!(matches!(err, StakingError::InvalidNominationTarget));
+
+        staking.bond(&mut balance, &alice, 100).unwrap();
+        staking.validate(&alice).unwrap();
+        staking.nominate(&bob, vec![alice]).unwrap();
+    }
+
+    #[test]
+    fn elects_top_validators_at_era_boundary() {
+        let (mut staking, mut balance) = setup();
+        let [alice, bob, charlie, dave] = ["Alice", "Bob", "Charlie", "Dave"].map(String::from);
+
+        for (who, amount) in [(&alice, 100), (&bob, 200), (&charlie, 300), (&dave, 500)] {
+            staking.bond(&mut balance, who, amount).unwrap();
+        }
+        staking.validate(&alice).unwrap();
+        staking.validate(&bob).unwrap();
+        staking.validate(&charlie).unwrap();
+        staking.nominate(&dave, vec![alice.clone()]).unwrap();
+
+        run_to_block(&mut staking, &mut balance, 1, 1);
+        assert_eq!(staking.current_era(), 1);
+        assert_eq!(staking.elected(), vec![alice.clone(), charlie.clone()]);
+        assert_eq!(
+            staking.exposure(&alice),
+            Some(&Exposure {
+                own: 100,
+                others: vec![(dave.clone(), 500)],
+                total: 600,
+            })
+        );
+
+        // The election only changes at the next era boundary.
+        staking.chill(&alice);
+        run_to_block(&mut staking, &mut balance, 2, 5);
+        assert_eq!(staking.elected(), vec![alice, charlie.clone()]);
+
+        run_to_block(&mut staking, &mut balance, 6, 6);
+        assert_eq!(staking.current_era(), 2);
+        assert_eq!(staking.elected(), vec![bob, charlie]);
+    }
+
+    #[test]
+    fn rewards_are_minted_at_end_of_era() {
+        let (mut staking, mut balance) = setup();
+        let [alice, bob, charlie, _] = ["Alice", "Bob", "Charlie", "Dave"].map(String::from);
+
+        staking.bond(&mut balance, &alice, 300).unwrap();
+        staking.bond(&mut balance, &bob, 100).unwrap();
+        staking.validate(&alice).unwrap();
+        staking.nominate(&bob, vec![alice.clone()]).unwrap();
+
+        run_to_block(&mut staking, &mut balance, 1, 4);
+        assert_eq!(balance.balance(&alice), 700);
+        assert_eq!(balance.balance(&bob), 900);
+
+        run_to_block(&mut staking, &mut balance, 5, 5);
+        assert_eq!(balance.balance(&alice), 1_450);
+        assert_eq!(balance.balance(&bob), 1_150);
+        assert_eq!(balance.balance(&charlie), 1_000);
+    }
+
+    #[test]
+    fn failed_payout_pays_nobody() {
+        let (mut staking, mut balance) = (Pallet::<TestConfig>::new(), balance::Pallet::new());
+        let [alice, bob] = ["Alice", "Bob"].map(String::from);
+        balance.set_balance(&alice, 300).unwrap();
+        balance.set_balance(&bob, 100).unwrap();
+
+        staking.bond(&mut balance, &alice, 300).unwrap();
+        staking.bond(&mut balance, &bob, 100).unwrap();
+        staking.validate(&alice).unwrap();
+        staking.nominate(&bob, vec![alice.clone()]).unwrap();
+        run_to_block(&mut staking, &mut balance, 1, 4);
+        // Bob's share of the reward no longer fits in his balance.
+        balance.set_balance(&bob, u128::MAX - 200).unwrap();
+
+        let mut pallets = (&mut staking, &mut balance);
+        assert!(pallets.on_finalize(5).is_err());
+        assert_eq!(balance.balance(&alice), 0);
+        assert_eq!(balance.balance(&bob), u128::MAX - 200);
+    }
+
+    #[test]
+    fn hooks_weight_depends_on_era_work() {
+        let (mut staking, mut balance) = setup();
+        let alice = String::from("Alice");
+        let bob = String::from("Bob");
+
+        staking.bond(&mut balance, &alice, 100).unwrap();
+        staking.bond(&mut balance, &bob, 100).unwrap();
+        staking.validate(&alice).unwrap();
+        staking.nominate(&bob, vec![alice]).unwrap();
+
+        let mut pallets = (&mut staking, &mut balance);
+        assert_eq!(pallets.on_initialize(1).unwrap(), 21);
+        assert_eq!(pallets.on_finalize(1).unwrap(), 1);
+        assert_eq!(pallets.on_initialize(2).unwrap(), 1);
+        run_to_block(&mut staking, &mut balance, 2, 4);
+
+        let mut pallets = (&mut staking, &mut balance);
+        assert_eq!(pallets.on_initialize(5).unwrap(), 1);
+        assert_eq!(pallets.on_finalize(5).unwrap(), 21);
+    }
+
+    #[test]
+    fn migrate_v0_drops_block_number() {
+        let (mut staking, mut balance) = setup();
+        let alice = String::from("Alice");
+        staking.bond(&mut balance, &alice, 400).unwrap();
+        staking.unbond(&alice, 100, 2).unwrap();
+
+        // Version 0 kept its own copy of the block number in front of the other fields.
+        let mut data = 2u32.encode();
+        data.extend(staking.encode());
+        let storage = PalletStorage { version: 0, data };
+
+        let (migrated, weight) = Pallet::<TestConfig>::migrate(storage).unwrap();
+        assert_eq!(weight, 1);
+        assert_eq!(migrated.bonded(&alice), 300);
+        assert_eq!(migrated.unlocking(&alice), &[(100, 5)]);
+    }
+
+    #[test]
+    fn dispatch_staking_calls() {
+        let (mut staking, mut balance) = setup();
+        let system = system::Pallet::new();
+        let alice = String::from("Alice");
+        let bob = String::from("Bob");
+
+        let mut pallets = (&mut staking, &mut balance, &system);
+        pallets
+            .dispatch(&alice, Call::Bond { amount: 100 })
+            .unwrap();
+        pallets.dispatch(&alice, Call::Validate).unwrap();
+        pallets.dispatch(&bob, Call::Bond { amount: 50 }).unwrap();
+        pallets
+            .dispatch(
+                &bob,
+                Call::Nominate {
+                    targets: vec![alice.clone()],
+                },
+            )
+            .unwrap();
+        pallets.dispatch(&bob, Call::Unbond { amount: 50 }).unwrap();
+        pallets.dispatch(&bob, Call::WithdrawUnbonded).unwrap();
+
+        assert_eq!(staking.bonded(&alice), 100);
+        assert_eq!(staking.bonded(&bob), 0);
+        assert_eq!(staking.unlocking(&bob), &[(50, 3)]);
+        assert_eq!(balance.balance(&bob), 950);
+    }
+}
@@ -0,0 +1,175 @@
+use alloc::vec::Vec;
+
+use codec::{Decode, Encode};
+use thiserror::Error;
+
+use crate::support::{Migrate, StorageVersion};
+use crate::system;
+
+#[derive(Debug, Error)]
+pub enum SudoError {
+    #[error("Caller is not the sudo key")]
+    RequireSudo,
+    #[error("Runtime code cannot be empty")]
+    EmptyCode,
+}
+
+pub trait Config: system::Config {}
+
+/// This is the Sudo Module.
+/// A single account, the sudo key, is allowed to perform privileged operations such as replacing
+/// the runtime code on-chain.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct Pallet<T: Config> {
+    key: Option<T::AccountId>,
+}
+
+impl<T: Config> Pallet<T> {
+    pub fn new() -> Self {
+        Self { key: None }
+    }
+
+    pub fn key(&self) -> Option<&T::AccountId> {
+        self.key.as_ref()
+    }
+
+    pub fn set_key(&mut self, key: Option<T::AccountId>) {
+        self.key = key;
+    }
+
+    fn ensure_sudo(&self, caller: &T::AccountId) -> Result<(), SudoError> {
+        match &self.key {
+            Some(key) if key == caller => Ok(()),
+            _ => Err(SudoError::RequireSudo),
+        }
+    }
+
+    /// Schedules `code` as the new runtime code. The host executes the following blocks with it.
+    pub fn set_code(
+        &self,
+        system: &mut system::Pallet<T>,
+        caller: &T::AccountId,
+        code: Vec<u8>,
+    ) -> Result<(), SudoError> {
+        self.ensure_sudo(caller)?;
+        if code.is_empty() {
+            return Err(SudoError::EmptyCode);
+        }
+
+        system.set_code(code);
+        Ok(())
+    }
+
+    /// Hands the sudo key over to `new`.
+    pub fn transfer_key(
+        &mut self,
+        caller: &T::AccountId,
+        new: T::AccountId,
+    ) -> Result<(), SudoError> {
+        self.ensure_sudo(caller)?;
+        self.key = Some(new);
+        Ok(())
+    }
+}
+
+impl<T: Config> Default for Pallet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Config> crate::support::Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> Migrate for Pallet<T> {
+    const STORAGE_VERSION: StorageVersion = 0;
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum Call<T: Config> {
+    SetCode { code: Vec<u8> },
+    TransferKey { new: T::AccountId },
+}
+
+/// Setting the code goes through the system pallet, so sudo calls are dispatched on both.
+impl<'a, T: Config> crate::support::Dispatch<'a> for (&mut Pallet<T>, &mut system::Pallet<T>)
+where
+    T::AccountId: 'a,
+{
+    type Caller = &'a T::AccountId;
+    type Call = Call<T>;
+
+    fn dispatch(
+        &mut self,
+        caller: Self::Caller,
+        call: Self::Call,
+    ) -> crate::support::DispatchResult {
+        let (sudo, system) = self;
+        match call {
+            Call::SetCode { code } => sudo.set_code(system, caller, code)?,
+            Call::TransferKey { new } => sudo.transfer_key(caller, new)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::support::Dispatch;
+
+    struct TestConfig;
+
+    impl system::Config for TestConfig {
+        type AccountId = String;
+        type Nonce = u32;
+        type BlockNumber = u32;
+    }
+
+    impl Config for TestConfig {}
+
+    fn setup() -> (
+        String,
+        String,
+        Pallet<TestConfig>,
+        system::Pallet<TestConfig>,
+    ) {
+        let alice = String::from("Alice");
+        let mut sudo = Pallet::new();
+        sudo.set_key(Some(alice.clone()));
+        (alice, String::from("Bob"), sudo, system::Pallet::new())
+    }
+
+    #[test]
+    fn only_sudo_key_sets_code() {
+        let (alice, bob, sudo, mut system) = setup();
+
+        let err = sudo.set_code(&mut system, &bob, vec![1, 2, 3]).unwrap_err();
+        assert!(matches!(err, SudoError::RequireSudo));
+        let err = sudo.set_code(&mut system, &alice, vec![]).unwrap_err();
+        assert!(matches!(err, SudoError::EmptyCode));
+        assert_eq!(system.take_pending_code(), None);
+
+        sudo.set_code(&mut system, &alice, vec![1, 2, 3]).unwrap();
+        assert_eq!(system.take_pending_code(), Some(vec![1, 2, 3]));
+        assert_eq!(system.take_pending_code(), None);
+    }
+
+    #[test]
+    fn dispatch_sudo_calls() {
+        let (alice, bob, mut sudo, mut system) = setup();
+
+        let mut pallets = (&mut sudo, &mut system);
+        pallets
+            .dispatch(&alice, Call::TransferKey { new: bob.clone() })
+            .unwrap();
+        assert!(pallets
+            .dispatch(&alice, Call::SetCode { code: vec![1] })
+            .is_err());
+        pallets
+            .dispatch(&bob, Call::SetCode { code: vec![1] })
+            .unwrap();
+
+        assert_eq!(sudo.key(), Some(&bob));
+        assert_eq!(system.take_pending_code(), Some(vec![1]));
+    }
+}
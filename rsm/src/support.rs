@@ -1,6 +1,10 @@
+use alloc::vec::Vec;
+
+use codec::{Decode, DecodeAll, Encode};
 use thiserror::Error;
 
 /// The most primitive representation of a Blockchain block.
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Block<Header, Extrinsic> {
     /// The block header contains metadata about the block.
     pub header: Header,
@@ -14,6 +18,7 @@ pub struct Block<Header, Extrinsic> {
 /// - state root
 /// - extrinsics root
 /// - etc...
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Header<BlockNumber> {
     pub block_number: BlockNumber,
 }
@@ -21,6 +26,7 @@ pub struct Header<BlockNumber> {
 /// This is an "extrinsic": literally an external message from outside of the blockchain.
 /// This simplified version of an extrinsic tells us who is making the call, and which call they are
 /// making.
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Extrinsic<Caller, Call> {
     pub caller: Caller,
     pub call: Call,
@@ -36,11 +42,21 @@ pub enum DispatchError {
     TransferError(#[from] crate::balance::TransferError),
     #[error(transparent)]
     ClaimError(#[from] crate::proof_of_existence::ClaimError),
+    #[error(transparent)]
+    StakingError(#[from] crate::staking::StakingError),
+    #[error(transparent)]
+    SudoError(#[from] crate::sudo::SudoError),
+    #[error(transparent)]
+    MigrationError(#[from] MigrationError),
     #[error("Block number mismatch")]
     BlockNumberMismatch,
 }
 pub type DispatchResult = Result<(), DispatchError>;
 
+/// An abstract measure of the computational time an operation takes. Each block can only consume
+/// up to a maximum weight, shared between the pallet hooks and the extrinsics of the block.
+pub type Weight = u64;
+
 /// A trait which allows us to dispatch an incoming extrinsic to the appropriate state transition
 /// function call.
 pub trait Dispatch<'a> {
@@ -53,3 +69,130 @@ pub trait Dispatch<'a> {
     /// based on the outcome of that function call.
     fn dispatch(&mut self, caller: Self::Caller, call: Self::Call) -> DispatchResult;
 }
+
+/// Per-block logic a pallet runs around the extrinsics of every block, such as expiring claims,
+/// distributing rewards or pruning storage.
+///
+/// The runtime calls `on_initialize` on every pallet, in a fixed order, before executing the
+/// extrinsics of a block, and `on_finalize`, in the same order, after them. Both return the weight
+/// they consumed, which counts against the block weight limit.
+pub trait Hooks<BlockNumber> {
+    /// Runs at the start of a block, before any extrinsic is executed.
+    fn on_initialize(&mut self, _block_number: BlockNumber) -> Result<Weight, DispatchError> {
+        Ok(0)
+    }
+
+    /// Runs at the end of a block, after every extrinsic was executed.
+    fn on_finalize(&mut self, _block_number: BlockNumber) -> Result<Weight, DispatchError> {
+        Ok(0)
+    }
+}
+
+/// The version of the runtime logic. It is stored with the state, which is rejected when it was
+/// written by a newer `spec_version` than the one of the code. Older state is upgraded by the
+/// storage migrations of every pallet whose storage version is behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeVersion {
+    pub spec_name: &'static str,
+    pub spec_version: u32,
+}
+
+/// Storage key under which the host keeps the WebAssembly code of the runtime.
+pub const CODE_KEY: &[u8] = b":code";
+/// Storage key under which the WebAssembly runtime keeps its encoded state.
+pub const STATE_KEY: &[u8] = b":state";
+
+/// The version of the storage layout of a pallet.
+pub type StorageVersion = u16;
+
+/// The encoded storage of a single pallet, along with the version of its layout. Pallets are
+/// stored apart from each other, so each one can decode and migrate its own data even when the
+/// layout of another one changed.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct PalletStorage {
+    pub version: StorageVersion,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("State was written by spec version {found}, newer than the supported {supported}")]
+    FutureSpecVersion { found: u32, supported: u32 },
+    #[error("Storage version {found} is newer than the supported {supported}")]
+    FutureStorageVersion {
+        found: StorageVersion,
+        supported: StorageVersion,
+    },
+    #[error("No migration from storage version {0}")]
+    MissingMigration(StorageVersion),
+    #[error("Storage does not match its version: {0}")]
+    Corrupted(codec::Error),
+    #[error("Storage check failed after migration: {0}")]
+    PostUpgradeCheckFailed(&'static str),
+}
+
+impl From<codec::Error> for MigrationError {
+    fn from(error: codec::Error) -> Self {
+        MigrationError::Corrupted(error)
+    }
+}
+
+/// A pallet whose storage layout is versioned.
+///
+/// Every time the layout changes, the pallet bumps `STORAGE_VERSION` and handles the previous
+/// version in `migrate_from`, so state written by older code can be upgraded one version at a
+/// time. The old layouts are kept as types of their own, like `balance::v0::Pallet`, to decode
+/// the data being migrated.
+pub trait Migrate: Encode + Decode {
+    /// The storage version the pallet code expects.
+    const STORAGE_VERSION: StorageVersion;
+
+    /// Upgrades `data`, encoded with storage version `from`, to the layout of `from + 1`.
+    /// Returns the new encoding and the weight used.
+    fn migrate_from(
+        from: StorageVersion,
+        _data: &[u8],
+    ) -> Result<(Vec<u8>, Weight), MigrationError> {
+        Err(MigrationError::MissingMigration(from))
+    }
+
+    /// Checks the invariants of the current storage layout once the migrations are done.
+    fn post_upgrade(&self) -> Result<(), MigrationError> {
+        Ok(())
+    }
+
+    /// The storage of the pallet, at the current storage version.
+    fn store(&self) -> PalletStorage {
+        PalletStorage {
+            version: Self::STORAGE_VERSION,
+            data: self.encode(),
+        }
+    }
+
+    /// Decodes the pallet from `storage`, after running every migration between its version and
+    /// `STORAGE_VERSION`. The invariants are checked when any migration ran.
+    fn migrate(storage: PalletStorage) -> Result<(Self, Weight), MigrationError> {
+        let found = storage.version;
+        if found > Self::STORAGE_VERSION {
+            return Err(MigrationError::FutureStorageVersion {
+                found,
+                supported: Self::STORAGE_VERSION,
+            });
+        }
+
+        let mut data = storage.data;
+        let mut weight = 0;
+        for from in found..Self::STORAGE_VERSION {
+            let (migrated, used) = Self::migrate_from(from, &data)?;
+            data = migrated;
+            weight += used;
+        }
+
+        let pallet = Self::decode_all(&mut data.as_slice())?;
+        if found < Self::STORAGE_VERSION {
+            pallet.post_upgrade()?;
+        }
+
+        Ok((pallet, weight))
+    }
+}
@@ -1,26 +1,48 @@
-use std::collections::BTreeMap;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
 
+use codec::{Decode, DecodeAll, Encode};
 use num::{CheckedAdd, CheckedSub, One, Zero};
 use thiserror::Error;
 
+use crate::support::{Migrate, MigrationError, StorageVersion, Weight};
+
 #[derive(Debug, Error)]
 pub enum BlockError {
     #[error("Overflow when incrementing block number")]
     OverflowIncrementBlockNumber,
     #[error("Overflow when incrementing nonce")]
     OverflowIncrementNonce,
+    #[error("Block weight limit exhausted")]
+    ExhaustsResources,
+}
+
+/// What happened to each extrinsic of the current block.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Event {
+    ExtrinsicSuccess { index: u32 },
+    ExtrinsicFailed { index: u32, error: String },
 }
 
 pub trait Config {
-    type AccountId: Ord + Clone;
-    type BlockNumber: Zero + One + CheckedAdd + Copy;
-    type Nonce: Zero + One + CheckedSub + CheckedAdd + Copy;
+    type AccountId: Ord + Clone + Encode + Decode;
+    type BlockNumber: Zero + One + CheckedAdd + Copy + Encode + Decode;
+    type Nonce: Zero + One + CheckedSub + CheckedAdd + Copy + Encode + Decode;
+
+    /// The maximum weight a single block can consume.
+    const MAX_BLOCK_WEIGHT: Weight = Weight::MAX;
+    /// The weight charged for executing any extrinsic.
+    const EXTRINSIC_BASE_WEIGHT: Weight = 0;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Pallet<T: Config> {
     bloc_number: T::BlockNumber,
     nonce: BTreeMap<T::AccountId, T::Nonce>, // Numver of transactions someone make on the blockchain
+    block_weight: Weight,                    // Weight consumed so far by the current block
+    pending_code: Option<Vec<u8>>,           // Runtime code scheduled to replace the current one
+    events: Vec<Event>,                      // Events deposited by the current block
 }
 
 impl<T: Config> Pallet<T> {
@@ -28,6 +50,9 @@ impl<T: Config> Pallet<T> {
         Self {
             bloc_number: T::BlockNumber::zero(),
             nonce: BTreeMap::new(),
+            block_weight: 0,
+            pending_code: None,
+            events: Vec::new(),
         }
     }
 
@@ -40,6 +65,46 @@ impl<T: Config> Pallet<T> {
             .bloc_number
             .checked_add(&T::BlockNumber::one())
             .ok_or(BlockError::OverflowIncrementBlockNumber)?;
+        self.block_weight = 0;
+        self.events.clear();
+
+        Ok(())
+    }
+
+    /// Schedules `code` to replace the runtime code once the current block is executed.
+    pub fn set_code(&mut self, code: Vec<u8>) {
+        self.pending_code = Some(code);
+    }
+
+    pub fn take_pending_code(&mut self) -> Option<Vec<u8>> {
+        self.pending_code.take()
+    }
+
+    pub fn deposit_event(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn block_weight(&self) -> Weight {
+        self.block_weight
+    }
+
+    /// Registers weight that must be executed regardless of the block limit, like the hooks.
+    pub fn register_mandatory_weight(&mut self, weight: Weight) {
+        self.block_weight = self.block_weight.saturating_add(weight);
+    }
+
+    /// Registers `weight` only if it still fits in the current block.
+    pub fn try_register_weight(&mut self, weight: Weight) -> Result<(), BlockError> {
+        let new_weight = self
+            .block_weight
+            .checked_add(weight)
+            .filter(|new_weight| *new_weight <= T::MAX_BLOCK_WEIGHT)
+            .ok_or(BlockError::ExhaustsResources)?;
+        self.block_weight = new_weight;
 
         Ok(())
     }
@@ -55,14 +120,69 @@ impl<T: Config> Pallet<T> {
         Ok(())
     }
 
+    /// Iterates over every account that sent at least one extrinsic.
+    pub fn nonces(&self) -> impl Iterator<Item = (&T::AccountId, &T::Nonce)> {
+        self.nonce.iter()
+    }
+
     pub fn get_nonce(&self, who: &T::AccountId) -> T::Nonce {
         *self.nonce.get(who).unwrap_or(&T::Nonce::zero())
     }
 }
 
+impl<T: Config> Default for Pallet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Config> crate::support::Hooks<T::BlockNumber> for Pallet<T> {}
+
+impl<T: Config> Migrate for Pallet<T> {
+    const STORAGE_VERSION: StorageVersion = 1;
+
+    fn migrate_from(
+        from: StorageVersion,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, Weight), MigrationError> {
+        match from {
+            // Version 1 added the code scheduled by sudo and the events of the current block.
+            0 => {
+                let v0::Pallet {
+                    bloc_number,
+                    nonce,
+                    block_weight,
+                } = v0::Pallet::<T>::decode_all(&mut &data[..])?;
+                let pallet = Pallet::<T> {
+                    bloc_number,
+                    nonce,
+                    block_weight,
+                    pending_code: None,
+                    events: Vec::new(),
+                };
+                Ok((pallet.encode(), pallet.nonce.len() as Weight))
+            }
+            _ => Err(MigrationError::MissingMigration(from)),
+        }
+    }
+}
+
+/// The storage layouts of older storage versions, decoded by the migrations.
+pub mod v0 {
+    use super::*;
+
+    #[derive(Decode)]
+    pub struct Pallet<T: Config> {
+        pub bloc_number: T::BlockNumber,
+        pub nonce: BTreeMap<T::AccountId, T::Nonce>,
+        pub block_weight: Weight,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::support::PalletStorage;
 
     struct TestConfig;
 
@@ -70,6 +190,8 @@ mod tests {
         type AccountId = String;
         type Nonce = u32;
         type BlockNumber = u128;
+
+        const MAX_BLOCK_WEIGHT: Weight = 100;
     }
 
     fn setup() -> Pallet<TestConfig> {
@@ -96,4 +218,51 @@ mod tests {
         let _ = system.increment_nonce(&alice);
         assert_eq!(system.get_nonce(&alice), 1);
     }
+
+    #[test]
+    fn block_weight_limit() {
+        let mut system = setup();
+        system.register_mandatory_weight(60);
+        system.try_register_weight(40).unwrap();
+        assert_eq!(system.block_weight(), 100);
+
+        let err = system.try_register_weight(1).unwrap_err();
+        assert!(matches!(err, BlockError::ExhaustsResources));
+        assert_eq!(system.block_weight(), 100);
+
+        system.register_mandatory_weight(10);
+        assert_eq!(system.block_weight(), 110);
+
+        system.increment_block_number().unwrap();
+        assert_eq!(system.block_weight(), 0);
+    }
+
+    #[test]
+    fn migrate_v0_adds_code_and_events() {
+        let alice = String::from("Alice");
+
+        // Version 0 had no pending code nor events.
+        let nonce = BTreeMap::from([(alice.clone(), 3u32)]);
+        let storage = PalletStorage {
+            version: 0,
+            data: (7u128, nonce, 40 as Weight).encode(),
+        };
+
+        let (system, weight) = Pallet::<TestConfig>::migrate(storage).unwrap();
+        assert_eq!(weight, 1);
+        assert_eq!(system.block_number(), 7);
+        assert_eq!(system.get_nonce(&alice), 3);
+        assert_eq!(system.block_weight(), 40);
+        assert!(system.events().is_empty());
+    }
+
+    #[test]
+    fn events_are_cleared_on_new_block() {
+        let mut system = setup();
+        system.deposit_event(Event::ExtrinsicSuccess { index: 0 });
+        assert_eq!(system.events(), &[Event::ExtrinsicSuccess { index: 0 }]);
+
+        system.increment_block_number().unwrap();
+        assert!(system.events().is_empty());
+    }
 }
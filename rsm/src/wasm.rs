@@ -0,0 +1,136 @@
+//! Entry points of the runtime when it is compiled to `wasm32-unknown-unknown` and executed by a
+//! host, like the `executor` module.
+//!
+//! The host owns the storage and exposes it through the `ext_storage_*` host functions. Buffers
+//! cross the boundary as a pointer and a length packed in a `u64`: `(ptr << 32) | len`. The host
+//! creates a fresh instance for every call, so memory never needs to be freed.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::arch::wasm32;
+use core::cell::UnsafeCell;
+
+use codec::{Decode, Encode};
+
+use crate::support::{CODE_KEY, STATE_KEY};
+use crate::{types, Runtime, State};
+
+const PAGE_SIZE: usize = 64 * 1024;
+/// Returned by `ext_storage_get` when there is no value under the key.
+const NO_VALUE: u64 = u64::MAX;
+
+mod host {
+    #[link(wasm_import_module = "env")]
+    extern "C" {
+        /// Copies the value stored under the key into memory obtained from `rsm_alloc`.
+        pub fn ext_storage_get(key_ptr: u32, key_len: u32) -> u64;
+        pub fn ext_storage_set(key_ptr: u32, key_len: u32, value_ptr: u32, value_len: u32);
+    }
+}
+
+/// An allocator that hands out memory past the end of the initial linear memory and never frees.
+struct BumpAllocator {
+    next: UnsafeCell<usize>,
+}
+
+// Wasm instances are single threaded.
+unsafe impl Sync for BumpAllocator {}
+
+unsafe impl GlobalAlloc for BumpAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let next = &mut *self.next.get();
+        if *next == 0 {
+            *next = wasm32::memory_size(0) * PAGE_SIZE;
+        }
+
+        let start = (*next + layout.align() - 1) & !(layout.align() - 1);
+        let end = start + layout.size();
+        let available = wasm32::memory_size(0) * PAGE_SIZE;
+        if end > available
+            && wasm32::memory_grow(0, (end - available).div_ceil(PAGE_SIZE)) == usize::MAX
+        {
+            return core::ptr::null_mut();
+        }
+
+        *next = end;
+        start as *mut u8
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+#[global_allocator]
+static ALLOCATOR: BumpAllocator = BumpAllocator {
+    next: UnsafeCell::new(0),
+};
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    wasm32::unreachable()
+}
+
+fn pack(buffer: Vec<u8>) -> u64 {
+    let packed = ((buffer.as_ptr() as u64) << 32) | buffer.len() as u64;
+    core::mem::forget(buffer);
+    packed
+}
+
+fn storage_get(key: &[u8]) -> Option<Vec<u8>> {
+    let packed = unsafe { host::ext_storage_get(key.as_ptr() as u32, key.len() as u32) };
+    if packed == NO_VALUE {
+        return None;
+    }
+
+    let (ptr, len) = ((packed >> 32) as usize, packed as u32 as usize);
+    Some(unsafe { Vec::from_raw_parts(ptr as *mut u8, len, len) })
+}
+
+fn storage_set(key: &[u8], value: &[u8]) {
+    unsafe {
+        host::ext_storage_set(
+            key.as_ptr() as u32,
+            key.len() as u32,
+            value.as_ptr() as u32,
+            value.len() as u32,
+        )
+    }
+}
+
+/// Allocates `len` bytes for the host to write into.
+#[no_mangle]
+pub extern "C" fn rsm_alloc(len: u32) -> u32 {
+    let mut buffer = Vec::<u8>::with_capacity(len as usize);
+    let ptr = buffer.as_mut_ptr();
+    core::mem::forget(buffer);
+    ptr as u32
+}
+
+/// Executes the encoded block at `ptr` on top of the state kept by the host and returns an encoded
+/// `Result<(), String>`. The state is only written back when the whole block succeeds.
+#[no_mangle]
+pub extern "C" fn rsm_execute_block(ptr: u32, len: u32) -> u64 {
+    let mut input = unsafe { core::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    pack(execute_block(&mut input).encode())
+}
+
+fn execute_block(input: &mut &[u8]) -> Result<(), String> {
+    let block = types::Block::decode(input).map_err(|e| e.to_string())?;
+    let mut runtime = match storage_get(STATE_KEY) {
+        Some(state) => {
+            let state = State::decode(&mut state.as_slice()).map_err(|e| e.to_string())?;
+            let (runtime, _) = Runtime::on_runtime_upgrade(state).map_err(|e| e.to_string())?;
+            runtime
+        }
+        None => Runtime::new(),
+    };
+
+    runtime.execute_block(block).map_err(|e| e.to_string())?;
+
+    if let Some(code) = runtime.system.take_pending_code() {
+        storage_set(CODE_KEY, &code);
+    }
+    storage_set(STATE_KEY, &runtime.state().encode());
+
+    Ok(())
+}